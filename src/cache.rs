@@ -1,135 +1,905 @@
 use crate::log::{error, info, warning};
+use crate::search::Source;
+use crate::storage;
+use crate::utils::{normalize_query, now};
+use crate::youtube::SearchResult;
+use clap::ValueEnum;
 use dirs::cache_dir;
-use std::collections::HashMap;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
 use std::fs;
+use std::time::Duration;
 
-/// Cache file for storing querys and urls.
-const CACHE_FILE: &str = "{}/playit";
+/// Database file for storing querys and their search results.
+const CACHE_FILE: &str = "{}/playit.db";
 
-/// Represents url cache for entire program.
+/// JSON cache file of older versions, imported into the database once.
+const LEGACY_CACHE_FILE: &str = "{}/playit";
+
+/// Default time-to-live of cache entries in days.
+pub const DEFAULT_TTL_DAYS: u64 = 30;
+
+/// Version of the database schema, stored as its `user_version`.
+const SCHEMA_VERSION: u32 = 1;
+
+/// The first version of the JSON cache file format with normalized queries.
+const NORMALIZED_VERSION: u32 = 3;
+
+/// Minimum similarity (from 0 to 1) of the queries offered as close matches.
+const SIMILARITY_THRESHOLD: f64 = 0.75;
+
+/// How long to wait for other playit processes writing to the database.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The tables and indexes of the database.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS entries (
+        query TEXT PRIMARY KEY,
+        sources TEXT NOT NULL,
+        url TEXT NOT NULL,
+        result TEXT NOT NULL,
+        resolved_at INTEGER NOT NULL,
+        last_used INTEGER NOT NULL,
+        broken INTEGER NOT NULL DEFAULT 0,
+        pinned INTEGER NOT NULL DEFAULT 0
+    );
+    CREATE INDEX IF NOT EXISTS entries_url ON entries (url);
+    CREATE INDEX IF NOT EXISTS entries_sources ON entries (sources);
+    CREATE TABLE IF NOT EXISTS counters (
+        name TEXT PRIMARY KEY,
+        value INTEGER NOT NULL
+    );
+";
+
+/// The columns of an entry, in the order `entry_from_row` reads them.
+const ENTRY_COLUMNS: &str = "query, result, resolved_at, last_used, broken, pinned";
+
+/// The condition of the entries expired for a time-to-live (`?1`) at a time (`?2`).
+const EXPIRED: &str = "(pinned = 0 AND ?1 > 0 AND resolved_at < ?2 - ?1)";
+
+/// Represents url cache for entire program, stored in a SQLite database.
+///
+/// Every change is written to the database right away, so other playit processes see it.
 ///
 /// # Fields
-/// - `path`: A string representing the path to the cache file.
-/// - `items`: A HashMap<String, String> containing querys and URLs.
+/// - `path`: A string representing the path to the database file.
+/// - `connection`: The connection to the database.
+/// - `ttl`: The time-to-live of entries in seconds. Entries never expire if it is 0.
 pub struct Cache {
     pub path: String,
-    pub items: HashMap<String, String>,
+    connection: Connection,
+    pub ttl: u64,
 }
 
-impl Default for Cache {
-    fn default() -> Self {
-        Self::new()
+/// A search result stored in the cache.
+///
+/// # Fields
+/// - `result`: The search result.
+/// - `resolved_at`: The UNIX timestamp of when the query was searched.
+/// - `last_used`: The UNIX timestamp of when the entry was last used.
+/// - `broken`: Whether the media of the entry is known to be unplayable (e.g., deleted).
+/// - `pinned`: Whether the entry was set by user. Pinned entries never expire.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CacheEntry {
+    #[serde(flatten)]
+    pub result: SearchResult,
+    #[serde(default)]
+    pub resolved_at: u64,
+    #[serde(default)]
+    pub last_used: u64,
+    #[serde(default)]
+    pub broken: bool,
+    #[serde(default)]
+    pub pinned: bool,
+}
+
+impl CacheEntry {
+    /// Checks if the entry is older than the given time-to-live (in seconds, 0 for never).
+    pub fn is_expired(&self, ttl: u64) -> bool {
+        !self.pinned && ttl > 0 && now().saturating_sub(self.resolved_at) > ttl
     }
 }
 
+/// The counts of the cache entries and lookups.
+///
+/// # Fields
+/// - `entries`: The count of entries.
+/// - `pinned`: The count of pinned entries.
+/// - `broken`: The count of broken entries.
+/// - `expired`: The count of expired entries.
+/// - `hits`: The count of lookups which found a valid entry.
+/// - `misses`: The count of lookups which didn't find a valid entry.
+pub struct CacheStats {
+    pub entries: u64,
+    pub pinned: u64,
+    pub broken: u64,
+    pub expired: u64,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// The content of a JSON cache file.
+#[derive(Deserialize)]
+struct CacheFile {
+    version: u32,
+    #[serde(default)]
+    hits: u64,
+    #[serde(default)]
+    misses: u64,
+    entries: HashMap<String, CachedItem>,
+}
+
+/// The JSON cache file formats which can be imported.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CacheContent {
+    /// The last format, with the lookup counts.
+    Versioned(CacheFile),
+    /// The first formats, which are a map of queries to entries.
+    Legacy(HashMap<String, CachedItem>),
+}
+
+/// A cached search result, as stored in the JSON cache file.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CachedItem {
+    /// The metadata of the result and the timestamps.
+    Entry(CacheEntry),
+    /// The first format, which is only the URL.
+    Url(String),
+}
+
 impl Cache {
-    /// Creates a new cache object. The cache file is saved as a '.json' file inside the user's
-    /// cache directory
+    /// Opens the cache database, creating it if it doesn't exist. The JSON cache of older
+    /// versions is imported into a new database.
+    ///
+    /// # Parameters
+    /// - `path`: The path of the database given by user. Defaults to `$XDG_CACHE_HOME/playit.db`.
     ///
     /// # Returns
-    /// A new `Cache` instance with the cache file path and an empty HashMap.
-    pub fn new() -> Self {
-        // Construct the path for the cache file.
-        let path: String = match cache_dir() {
-            Some(pathbuf) => CACHE_FILE.replace("{}", &pathbuf.display().to_string()),
-            None => {
-                warning("Unable to retrieve the cache directory");
-                String::new()
-            }
+    /// - `Ok(Cache)`: The cache, with the default time-to-live.
+    /// - `Err(String)`: An error message if the database couldn't be opened or created.
+    pub fn open(path: Option<&str>) -> Result<Self, String> {
+        let dir = cache_dir().map(|dir| dir.display().to_string());
+        let path = match (path, &dir) {
+            (Some(path), _) => path.to_string(),
+            (None, Some(dir)) => CACHE_FILE.replace("{}", dir),
+            (None, None) => return Err("Unable to retrieve the cache directory".to_string()),
         };
-        Cache {
+
+        let connection = Connection::open(&path)
+            .map_err(|e| format!("Unable to open the cache database at {}: {}", path, e))?;
+        let mut cache = Cache {
             path,
-            items: HashMap::new(),
-        }
+            connection,
+            ttl: DEFAULT_TTL_DAYS * 24 * 60 * 60,
+        };
+        let legacy_path = dir.map(|dir| LEGACY_CACHE_FILE.replace("{}", &dir));
+        cache
+            .prepare(legacy_path.as_deref())
+            .map_err(|e| format!("Unable to prepare the cache database: {}", e))?;
+        info(format!("Cache opened from: {}", cache.path));
+        Ok(cache)
     }
 
-    /// Reads the cache file and loads its content into `items` as a HashMap<String, String>
+    /// Changes the time-to-live of the entries.
     ///
-    /// # Returns
-    /// - `Ok(())` if the file was successfully read and parsed.
-    /// - `Err(String)` if there was an error reading or parsing the file
-    pub fn read(&mut self) -> Result<(), String> {
-        // Check if file exists on the path
-        // If not create the file for avoiding errors
-        if !fs::exists(&self.path).unwrap_or(false) {
-            match fs::write(&self.path, "{}") {
-                Ok(()) => {
-                    info("Cache file created for playit.");
-                }
-                Err(e) => {
-                    warning("Unable to create cache file for playit.");
-                    error(&e);
-                    return Err("Unable to create cache file".to_string());
-                }
-            }
+    /// Very long times are capped, as the database stores them as signed 64-bit integers.
+    ///
+    /// # Parameters
+    /// - `days`: The days after which entries expire. Entries never expire if it is 0.
+    pub fn set_ttl_days(&mut self, days: u64) {
+        self.ttl = days.saturating_mul(24 * 60 * 60).min(i64::MAX as u64);
+    }
+
+    /// Sets up the connection and the schema, importing the JSON cache into a new database.
+    fn prepare(&mut self, legacy_path: Option<&str>) -> rusqlite::Result<()> {
+        // Other playit processes are waited for, and they can read while one of them writes
+        self.connection.busy_timeout(BUSY_TIMEOUT)?;
+        self.connection.pragma_update(None, "journal_mode", "WAL")?;
+
+        let version: u32 = self
+            .connection
+            .pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version >= SCHEMA_VERSION {
+            return Ok(());
         }
-        // Attempt to read the cache file into a string.
-        let content = match fs::read_to_string(&self.path) {
-            Ok(content) => content,
-            Err(_) => {
-                error(format!("Failed to read the cache file at: {}", self.path));
-                return Err("Error reading the cache file.".to_string());
-            }
-        };
-        // Deserialize the content into a HashMap<String, String>
-        //                                       (query)  (url)
-        match serde_json::from_str::<HashMap<String, String>>(&content) {
-            Ok(data) => {
-                self.items = data;
-                info(format!("Cache loaded successfully from: {}", self.path));
-                Ok(())
-            }
-            Err(_) => {
-                error(format!(
-                    "Failed to parse the cache JSON from: {}",
-                    self.path
-                ));
-                Err("Failed to parse the cache JSON. It might be corrupted.".to_string())
+        let transaction = self.connection.transaction()?;
+        transaction.execute_batch(SCHEMA)?;
+        // The version is checked again, as another process may have created the schema
+        let version: u32 =
+            transaction.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version < SCHEMA_VERSION {
+            if let Some(legacy_path) = legacy_path.filter(|path| fs::exists(path).unwrap_or(false))
+            {
+                import(&transaction, legacy_path)?;
             }
+            transaction.pragma_update(None, "user_version", SCHEMA_VERSION)?;
         }
+        transaction.commit()
     }
 
     /// Adds a new item to cache.
     ///
     /// # Parameters
     /// - `query`: A string slice containing user given query for key.
-    /// - `url`: A string slice containing search result of query for value.
+    /// - `result`: The search result of query for value.
     ///
     /// # Returns
-    /// This function does not return a value. It updates the cache items.
-    pub fn add(&mut self, query: &str, url: &str) {
-        self.items.insert(query.to_string(), url.to_string());
-        info(format!("Added url with query {} to cache: {}", query, url));
+    /// - `Ok(())` if the item was saved.
+    /// - `Err(String)` if the database couldn't be written.
+    pub fn add(&mut self, query: &str, result: &SearchResult) -> Result<(), String> {
+        self.insert(query, result, false)?;
+        info(format!(
+            "Added url with query {} to cache: {}",
+            query, result.url
+        ));
+        Ok(())
     }
 
-    /// Writes the cache items to the cache file as a JSON string.
+    /// Returns the cached result of a query, and records that it is used.
+    ///
+    /// Expired and broken entries are not returned, so the query is searched again.
+    ///
+    /// # Parameters
+    /// - `query`: The query to look up.
     ///
     /// # Returns
-    /// - `Ok(())` if the file was successfully written.
-    /// - `Err(String)` if there was an error writing the file.
-    pub fn write(self) -> Result<Self, String> {
-        // Serialize the cache items into a JSON string.
-        let content = match serde_json::to_string(&self.items) {
-            Ok(content) => content,
-            Err(_) => {
-                error("Failed to convert cache items to string for writing to file.");
-                return Err("Failed to convert cache items to string.".to_string());
+    /// - `Some(SearchResult)` if the query is cached and the entry is still valid.
+    /// - `None` if the query is not cached, or the entry is expired or broken.
+    pub fn get(&mut self, query: &str) -> Option<SearchResult> {
+        let result = match self.entry(query) {
+            Some(entry) if entry.broken => {
+                info("The cached media is broken, it will be searched again.");
+                None
             }
+            Some(entry) if entry.is_expired(self.ttl) => {
+                info("The cached URL is expired, it will be searched again.");
+                None
+            }
+            Some(entry) => Some(entry.result),
+            None => None,
         };
 
-        // Write the cache to the playlist file.
-        match fs::write(&self.path, content) {
-            Ok(_) => {
-                info(format!("Cache successfully written to: {}", self.path)); // Log successful write
-                Ok(self)
-            }
-            Err(e) => {
-                error(format!(
-                    "Error writing to cache file at {}: {}",
-                    self.path, e
-                )); // Log error during write
-                Err("Failed to write cache to file.".to_string())
+        let recorded = match result {
+            Some(_) => self
+                .connection
+                .execute(
+                    "UPDATE entries SET last_used = ?1 WHERE query = ?2",
+                    params![now(), query],
+                )
+                .and_then(|_| self.count("hits")),
+            None => self.count("misses"),
+        };
+        if let Err(e) = recorded {
+            warning("Unable to save the usage of the cache.");
+            error(e);
+        }
+        result
+    }
+
+    /// Returns the entry of a query, without recording that it is used.
+    ///
+    /// # Parameters
+    /// - `query`: The query to look up.
+    ///
+    /// # Returns
+    /// - `Some(CacheEntry)` if the query is cached, even if the entry is expired or broken.
+    /// - `None` if the query is not cached.
+    pub fn entry(&self, query: &str) -> Option<CacheEntry> {
+        self.connection
+            .query_row(
+                &format!("SELECT {} FROM entries WHERE query = ?1", ENTRY_COLUMNS),
+                params![query],
+                entry_from_row,
+            )
+            .optional()
+            .unwrap_or_else(|e| {
+                warning("Unable to read the cache.");
+                error(e);
+                None
+            })
+            .map(|(_, entry)| entry)
+    }
+
+    /// Returns every entry of the cache, sorted by query.
+    ///
+    /// # Returns
+    /// - `Ok(Vec<(String, CacheEntry)>)`: The queries with their entries.
+    /// - `Err(String)`: An error message if the database couldn't be read.
+    pub fn entries(&self) -> Result<Vec<(String, CacheEntry)>, String> {
+        let read = || -> rusqlite::Result<Vec<(String, CacheEntry)>> {
+            self.connection
+                .prepare(&format!(
+                    "SELECT {} FROM entries ORDER BY query",
+                    ENTRY_COLUMNS
+                ))?
+                .query_map([], entry_from_row)?
+                .collect()
+        };
+        read().map_err(|e| format!("Unable to read the cache: {}", e))
+    }
+
+    /// Returns the counts of the entries and lookups.
+    ///
+    /// # Returns
+    /// - `Ok(CacheStats)`: The counts.
+    /// - `Err(String)`: An error message if the database couldn't be read.
+    pub fn stats(&self) -> Result<CacheStats, String> {
+        let read = || -> rusqlite::Result<CacheStats> {
+            let (entries, pinned, broken, expired) = self.connection.query_row(
+                &format!(
+                    "SELECT COUNT(*), COALESCE(SUM(pinned), 0), COALESCE(SUM(broken), 0),
+                     COALESCE(SUM({}), 0) FROM entries",
+                    EXPIRED
+                ),
+                params![self.ttl, now()],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )?;
+            Ok(CacheStats {
+                entries,
+                pinned,
+                broken,
+                expired,
+                hits: self.counter("hits")?,
+                misses: self.counter("misses")?,
+            })
+        };
+        read().map_err(|e| format!("Unable to read the cache: {}", e))
+    }
+
+    /// Returns the cached queries which are close to a query, most similar first.
+    ///
+    /// Only the valid entries searched on the same sources as the query are considered.
+    ///
+    /// # Parameters
+    /// - `query`: The normalized query (cache key) which is not cached.
+    /// - `limit`: The maximum count of matches.
+    ///
+    /// # Returns
+    /// The close queries with their cached results.
+    pub fn similar(&self, query: &str, limit: usize) -> Vec<(String, SearchResult)> {
+        let (sources, text) = split_key(query);
+        let read = || -> rusqlite::Result<Vec<(String, CacheEntry)>> {
+            self.connection
+                .prepare(&format!(
+                    "SELECT {} FROM entries WHERE sources = ?3 AND query != ?4 AND broken = 0
+                     AND NOT {}",
+                    ENTRY_COLUMNS, EXPIRED
+                ))?
+                .query_map(params![self.ttl, now(), sources, query], entry_from_row)?
+                .collect()
+        };
+        let entries = read().unwrap_or_else(|e| {
+            warning("Unable to read the cache.");
+            error(e);
+            Vec::new()
+        });
+
+        let mut matches: Vec<(f64, String, SearchResult)> = entries
+            .into_iter()
+            .filter_map(|(key, entry)| {
+                let score = similarity(text, split_key(&key).1);
+                (score >= SIMILARITY_THRESHOLD).then_some((score, key, entry.result))
+            })
+            .collect();
+        matches.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+        matches
+            .into_iter()
+            .take(limit)
+            .map(|(_, key, result)| (key, result))
+            .collect()
+    }
+
+    /// Caches a query with the result of a similar query, keeping when it was resolved.
+    ///
+    /// # Parameters
+    /// - `query`: The query to cache.
+    /// - `similar`: The cached query whose result is used.
+    ///
+    /// # Returns
+    /// - `Ok(())` if the query was cached.
+    /// - `Err(String)` if the database couldn't be written.
+    pub fn link(&mut self, query: &str, similar: &str) -> Result<(), String> {
+        self.connection
+            .execute(
+                "INSERT OR REPLACE INTO entries
+                 SELECT ?1, sources, url, result, resolved_at, ?2, broken, 0
+                 FROM entries WHERE query = ?3",
+                params![query, now(), similar],
+            )
+            .map_err(|e| format!("Unable to write the cache: {}", e))?;
+        info(format!("Cached query {} as: {}", query, similar));
+        Ok(())
+    }
+
+    /// Pins a query to a result, so the query is never searched again.
+    ///
+    /// # Parameters
+    /// - `query`: The query to pin.
+    /// - `result`: The result to play for the query.
+    ///
+    /// # Returns
+    /// - `Ok(())` if the query was pinned.
+    /// - `Err(String)` if the database couldn't be written.
+    pub fn pin(&mut self, query: &str, result: &SearchResult) -> Result<(), String> {
+        self.insert(query, result, true)?;
+        info(format!("Pinned query {} to: {}", query, result.url));
+        Ok(())
+    }
+
+    /// Removes a query from the cache.
+    ///
+    /// # Returns
+    /// - `Ok(bool)`: `true` if the query was cached.
+    /// - `Err(String)`: An error message if the database couldn't be written.
+    pub fn remove(&mut self, query: &str) -> Result<bool, String> {
+        self.connection
+            .execute("DELETE FROM entries WHERE query = ?1", params![query])
+            .map(|count| count > 0)
+            .map_err(|e| format!("Unable to write the cache: {}", e))
+    }
+
+    /// Removes the expired and broken entries.
+    ///
+    /// # Returns
+    /// - `Ok(usize)`: The count of removed entries.
+    /// - `Err(String)`: An error message if the database couldn't be written.
+    pub fn prune(&mut self) -> Result<usize, String> {
+        self.connection
+            .execute(
+                &format!("DELETE FROM entries WHERE broken = 1 OR {}", EXPIRED),
+                params![self.ttl, now()],
+            )
+            .map_err(|e| format!("Unable to write the cache: {}", e))
+    }
+
+    /// Removes every entry and resets the lookup counts.
+    ///
+    /// # Returns
+    /// - `Ok(usize)`: The count of removed entries.
+    /// - `Err(String)`: An error message if the database couldn't be written.
+    pub fn clear(&mut self) -> Result<usize, String> {
+        let clear = |connection: &Connection| -> rusqlite::Result<usize> {
+            connection.execute("DELETE FROM counters", [])?;
+            connection.execute("DELETE FROM entries", [])
+        };
+        let count =
+            clear(&self.connection).map_err(|e| format!("Unable to write the cache: {}", e))?;
+        // Give the space of the removed entries back
+        if let Err(e) = self.connection.execute_batch("VACUUM") {
+            warning("Unable to shrink the cache database.");
+            error(e);
+        }
+        Ok(count)
+    }
+
+    /// Marks the entries with the given URL as broken, so their queries are searched again.
+    ///
+    /// # Parameters
+    /// - `url`: The URL of the media which can't be played.
+    ///
+    /// # Returns
+    /// - `Ok(bool)`: `true` if any entry was marked.
+    /// - `Err(String)`: An error message if the database couldn't be written.
+    pub fn mark_broken(&mut self, url: &str) -> Result<bool, String> {
+        // Pinned entries are chosen by user, keep them as they are
+        let count = self
+            .connection
+            .execute(
+                "UPDATE entries SET broken = 1 WHERE url = ?1 AND broken = 0 AND pinned = 0",
+                params![url],
+            )
+            .map_err(|e| format!("Unable to write the cache: {}", e))?;
+        if count > 0 {
+            info(format!("Marked cached URL as broken: {}", url));
+        }
+        Ok(count > 0)
+    }
+
+    /// Inserts or replaces the entry of a query with a result resolved now.
+    fn insert(&mut self, query: &str, result: &SearchResult, pinned: bool) -> Result<(), String> {
+        let time = now();
+        insert_entry(
+            &self.connection,
+            query,
+            &CacheEntry {
+                result: result.clone(),
+                resolved_at: time,
+                last_used: time,
+                broken: false,
+                pinned,
+            },
+        )
+        .map_err(|e| format!("Unable to write the cache: {}", e))
+    }
+
+    /// Increments a lookup counter.
+    fn count(&self, name: &str) -> rusqlite::Result<usize> {
+        self.connection.execute(
+            "INSERT INTO counters (name, value) VALUES (?1, 1)
+             ON CONFLICT (name) DO UPDATE SET value = value + 1",
+            params![name],
+        )
+    }
+
+    /// Returns the value of a lookup counter.
+    fn counter(&self, name: &str) -> rusqlite::Result<u64> {
+        self.connection
+            .query_row(
+                "SELECT value FROM counters WHERE name = ?1",
+                params![name],
+                |row| row.get(0),
+            )
+            .optional()
+            .map(Option::unwrap_or_default)
+    }
+}
+
+/// Imports the JSON cache file of older versions, keeping its entries and lookup counts.
+///
+/// The file is renamed after the import, so it is not used again.
+fn import(connection: &Connection, path: &str) -> rusqlite::Result<()> {
+    let content = match storage::read::<CacheContent>(path) {
+        Ok(content) => content,
+        Err(e) => {
+            warning("Unable to import the cache of the older version, it is ignored.");
+            error(e);
+            return Ok(());
+        }
+    };
+    let (items, version, hits, misses) = match content {
+        CacheContent::Versioned(file) => (file.entries, file.version, file.hits, file.misses),
+        CacheContent::Legacy(items) => (items, 0, 0, 0),
+    };
+
+    let time = now();
+    let mut entries: HashMap<String, CacheEntry> = HashMap::new();
+    for (query, item) in items {
+        let mut entry = match item {
+            CachedItem::Entry(entry) => entry,
+            CachedItem::Url(url) => CacheEntry {
+                result: SearchResult::from_url(&url),
+                resolved_at: 0,
+                last_used: 0,
+                broken: false,
+                pinned: false,
+            },
+        };
+        // Older versions didn't record the timestamps
+        if entry.resolved_at == 0 {
+            entry.resolved_at = time;
+        }
+        if entry.last_used == 0 {
+            entry.last_used = entry.resolved_at;
+        }
+        // Older versions used the queries as they were given. When several queries become
+        // the same, the pinned or the most recently used entry is kept.
+        let key = if version < NORMALIZED_VERSION {
+            normalize_key(&query)
+        } else {
+            query
+        };
+        match entries.get(&key) {
+            Some(kept) if (kept.pinned, kept.last_used) >= (entry.pinned, entry.last_used) => {}
+            _ => {
+                entries.insert(key, entry);
             }
         }
     }
+
+    for (query, entry) in &entries {
+        insert_entry(connection, query, entry)?;
+    }
+    for (name, value) in [("hits", hits), ("misses", misses)] {
+        connection.execute(
+            "INSERT OR REPLACE INTO counters (name, value) VALUES (?1, ?2)",
+            params![name, value],
+        )?;
+    }
+
+    let imported = format!("{}.imported", path);
+    if let Err(e) = fs::rename(path, &imported) {
+        warning("Unable to rename the imported cache file.");
+        error(e);
+    }
+    storage::remove_backup(path);
+    info(format!(
+        "Imported {} cached queries from: {}",
+        entries.len(),
+        path
+    ));
+    Ok(())
+}
+
+/// Inserts or replaces the entry of a query.
+fn insert_entry(connection: &Connection, query: &str, entry: &CacheEntry) -> rusqlite::Result<()> {
+    let result = serde_json::to_string(&entry.result)
+        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+    connection.execute(
+        "INSERT OR REPLACE INTO entries
+         (query, sources, url, result, resolved_at, last_used, broken, pinned)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            query,
+            split_key(query).0,
+            entry.result.url,
+            result,
+            entry.resolved_at,
+            entry.last_used,
+            entry.broken,
+            entry.pinned
+        ],
+    )?;
+    Ok(())
+}
+
+/// Reads a query and its entry from a row with the `ENTRY_COLUMNS`.
+fn entry_from_row(row: &Row) -> rusqlite::Result<(String, CacheEntry)> {
+    let result: String = row.get(1)?;
+    let result = serde_json::from_str(&result).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(1, rusqlite::types::Type::Text, Box::new(e))
+    })?;
+    Ok((
+        row.get(0)?,
+        CacheEntry {
+            result,
+            resolved_at: row.get(2)?,
+            last_used: row.get(3)?,
+            broken: row.get(4)?,
+            pinned: row.get(5)?,
+        },
+    ))
+}
+
+/// Normalizes a cache key of an older version, keeping its sources prefix (e.g., `sc:`).
+fn normalize_key(key: &str) -> String {
+    match split_key(key) {
+        ("", _) => normalize_query(key),
+        (sources, query) => format!("{}:{}", sources, normalize_query(query)),
+    }
+}
+
+/// Splits a cache key into its sources prefix (empty for YouTube) and its query.
+///
+/// Only names of sources make a prefix, so queries like `artist: song` are kept whole.
+fn split_key(key: &str) -> (&str, &str) {
+    match key.split_once(':') {
+        Some((sources, query))
+            if sources
+                .split(',')
+                .all(|name| Source::from_str(name, true).is_ok()) =>
+        {
+            (sources, query)
+        }
+        _ => ("", key),
+    }
+}
+
+/// Returns the similarity of two normalized queries, from 0 (different) to 1 (same).
+///
+/// It is the higher of the edit distance ratio, which catches typos, and the ratio of the shared
+/// words, which catches reordered and extra words.
+fn similarity(a: &str, b: &str) -> f64 {
+    let length = a.chars().count().max(b.chars().count());
+    if length == 0 {
+        return 1.0;
+    }
+    let edits = 1.0 - edit_distance(a, b) as f64 / length as f64;
+
+    let a_words: HashSet<&str> = a.split_whitespace().collect();
+    let b_words: HashSet<&str> = b.split_whitespace().collect();
+    let union = a_words.union(&b_words).count();
+    let words = if union == 0 {
+        0.0
+    } else {
+        a_words.intersection(&b_words).count() as f64 / union as f64
+    };
+    edits.max(words)
+}
+
+/// Returns the Levenshtein distance of two texts, in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let cost = usize::from(a_char != *b_char);
+            current[j + 1] = (previous[j] + cost)
+                .min(previous[j + 1] + 1)
+                .min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns an empty cache in memory, which never expires its entries.
+    fn memory() -> Cache {
+        let connection = Connection::open_in_memory().unwrap();
+        connection.execute_batch(SCHEMA).unwrap();
+        Cache {
+            path: String::from(":memory:"),
+            connection,
+            ttl: 0,
+        }
+    }
+
+    /// Imports a JSON cache file with the given content into an empty cache.
+    fn imported(name: &str, content: &str) -> Cache {
+        let path = std::env::temp_dir()
+            .join(format!("playit-cache-{}-{}", std::process::id(), name))
+            .display()
+            .to_string();
+        fs::write(&path, content).unwrap();
+        let cache = memory();
+        import(&cache.connection, &path).unwrap();
+        assert!(!fs::exists(&path).unwrap(), "The file should be renamed.");
+        fs::remove_file(format!("{}.imported", path)).unwrap();
+        cache
+    }
+
+    /// Returns the cached queries with the URLs of their results.
+    fn urls(cache: &Cache) -> Vec<(String, String)> {
+        cache
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|(query, entry)| (query, entry.result.url))
+            .collect()
+    }
+
+    fn pair(query: &str, url: &str) -> (String, String) {
+        (query.to_string(), url.to_string())
+    }
+
+    #[test]
+    fn caps_long_ttls() {
+        let mut cache = memory();
+        cache.set_ttl_days(DEFAULT_TTL_DAYS);
+        assert_eq!(cache.ttl, 30 * 24 * 60 * 60);
+        cache.set_ttl_days(u64::MAX);
+        assert_eq!(cache.ttl, i64::MAX as u64);
+        // The database accepts the capped time
+        cache.stats().unwrap();
+    }
+
+    #[test]
+    fn imports_urls() {
+        let cache = imported("urls", r#"{"Daft Punk - One More Time": "https://a"}"#);
+        assert_eq!(urls(&cache), [pair("daft punk one more time", "https://a")]);
+        let entry = cache.entry("daft punk one more time").unwrap();
+        assert!(entry.resolved_at > 0);
+        assert_eq!(entry.last_used, entry.resolved_at);
+    }
+
+    #[test]
+    fn imports_entries() {
+        let content = r#"{
+            "Around The World": {"url": "https://a", "title": "Around", "resolved_at": 5},
+            "sc:One More Time": {"url": "https://b", "resolved_at": 7, "last_used": 9,
+                                 "broken": true, "pinned": true}
+        }"#;
+        let cache = imported("entries", content);
+        assert_eq!(
+            urls(&cache),
+            [
+                pair("around the world", "https://a"),
+                pair("sc:one more time", "https://b")
+            ]
+        );
+        let entry = cache.entry("around the world").unwrap();
+        assert_eq!(entry.result.title.as_deref(), Some("Around"));
+        assert_eq!((entry.resolved_at, entry.last_used), (5, 5));
+        let entry = cache.entry("sc:one more time").unwrap();
+        assert_eq!((entry.resolved_at, entry.last_used), (7, 9));
+        assert!(entry.broken && entry.pinned);
+    }
+
+    #[test]
+    fn imports_versioned_files() {
+        let content = r#"{"version": 2, "hits": 3, "misses": 4,
+                          "entries": {"Da Funk": {"url": "https://a"}}}"#;
+        let cache = imported("versioned", content);
+        assert_eq!(urls(&cache), [pair("da funk", "https://a")]);
+        assert_eq!(cache.counter("hits").unwrap(), 3);
+        assert_eq!(cache.counter("misses").unwrap(), 4);
+
+        // Normalized files are imported as they are
+        let content = r#"{"version": 3, "entries": {"Da Funk": {"url": "https://a"}}}"#;
+        let cache = imported("normalized", content);
+        assert_eq!(urls(&cache), [pair("Da Funk", "https://a")]);
+    }
+
+    #[test]
+    fn merges_normalized_queries() {
+        // The pinned entry is kept, even if the other one is used more recently
+        let content = r#"{
+            "Da Funk": {"url": "https://pinned", "last_used": 5, "pinned": true},
+            "da funk": {"url": "https://recent", "last_used": 9},
+            "DA FUNK!": {"url": "https://old", "last_used": 7}
+        }"#;
+        let cache = imported("pinned", content);
+        assert_eq!(urls(&cache), [pair("da funk", "https://pinned")]);
+
+        // Otherwise the most recently used entry is kept
+        let content = r#"{
+            "Da Funk": {"url": "https://old", "last_used": 5},
+            "da funk": {"url": "https://recent", "last_used": 9},
+            "DA FUNK!": {"url": "https://older", "last_used": 3}
+        }"#;
+        let cache = imported("recent", content);
+        assert_eq!(urls(&cache), [pair("da funk", "https://recent")]);
+    }
+
+    #[test]
+    fn splits_keys() {
+        assert_eq!(split_key("daft punk"), ("", "daft punk"));
+        assert_eq!(split_key("sc:daft punk"), ("sc", "daft punk"));
+        assert_eq!(split_key("ytm,yt:daft punk"), ("ytm,yt", "daft punk"));
+        // Only sources make a prefix
+        assert_eq!(split_key("daft punk: da funk"), ("", "daft punk: da funk"));
+        assert_eq!(normalize_key("Daft Punk: Da Funk"), "daft punk da funk");
+        assert_eq!(normalize_key("SC:Da Funk"), "SC:da funk");
+    }
+
+    #[test]
+    fn similarities() {
+        let similar = |a: &str, b: &str| similarity(a, b) >= SIMILARITY_THRESHOLD;
+        // Typos
+        assert!(similar("daft punk one more time", "daft punk one more tme"));
+        assert!(similar(
+            "daft punk one more time",
+            "dfat punk one more time"
+        ));
+        // Reordered and extra words
+        assert!(similar(
+            "daft punk one more time",
+            "one more time daft punk"
+        ));
+        assert!(similar(
+            "daft punk one more time",
+            "daft punk one more time live"
+        ));
+        // Other songs of the same artist
+        assert!(!similar(
+            "daft punk one more time",
+            "daft punk around the world"
+        ));
+        assert!(!similar("daft punk one more time", "daft punk da funk"));
+        assert!(!similar("", "daft punk"));
+        assert_eq!(similarity("", ""), 1.0);
+    }
+
+    #[test]
+    fn offers_similar_queries_of_the_same_sources() {
+        let mut cache = memory();
+        for (query, url) in [
+            ("daft punk one more time", "https://a"),
+            ("sc:daft punk one more time", "https://b"),
+            ("daft punk around the world", "https://c"),
+        ] {
+            cache.add(query, &SearchResult::from_url(url)).unwrap();
+        }
+        let urls = |cache: &Cache, query: &str| -> Vec<String> {
+            cache
+                .similar(query, 5)
+                .into_iter()
+                .map(|(_, result)| result.url)
+                .collect()
+        };
+        assert_eq!(urls(&cache, "daft punk one more tme"), ["https://a"]);
+        assert_eq!(urls(&cache, "sc:one more time daft punk"), ["https://b"]);
+        assert!(urls(&cache, "ytm:daft punk one more time").is_empty());
+
+        // Broken entries are not offered
+        cache.mark_broken("https://a").unwrap();
+        assert!(urls(&cache, "daft punk one more tme").is_empty());
+    }
 }
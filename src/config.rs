@@ -0,0 +1,269 @@
+use crate::quality::{AudioFormat, Quality};
+use crate::search::Source;
+use dirs::config_dir;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+
+/// Configuration file for the defaults of the options.
+const CONFIG_FILE: &str = "{}/playit/config.toml";
+
+/// The name of the built-in profile for saving bandwidth.
+const DATA_SAVER: &str = "data-saver";
+
+/// The defaults of the options, read from the configuration file.
+///
+/// Every field is named after the option it sets (e.g., `show-video` for `--show-video`), and
+/// it is used only when the option isn't given on the command line or with its environment
+/// variable.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub show_video: Option<bool>,
+    pub only_video: Option<bool>,
+    pub volume: Option<u8>,
+    #[serde(rename = "loop")]
+    pub loop_audio: Option<bool>,
+    pub mute: Option<bool>,
+    pub shuffle: Option<bool>,
+    pub download: Option<bool>,
+    pub notification: Option<String>,
+    pub prefix: Option<String>,
+    pub socket: Option<String>,
+    pub daemon_socket: Option<String>,
+    pub pick: Option<usize>,
+    pub menu: Option<String>,
+    pub source: Option<Vec<Source>>,
+    pub music_dir: Option<String>,
+    pub offline: Option<bool>,
+    pub cache_ttl: Option<u64>,
+    pub fuzzy: Option<bool>,
+    pub mpv_path: Option<String>,
+    pub ytdlp_path: Option<String>,
+    pub cache_path: Option<String>,
+    pub quality: Option<Quality>,
+    pub audio_format: Option<AudioFormat>,
+    /// The profile used when no other profile is selected.
+    pub profile: Option<String>,
+    /// The named profiles, selected with `--profile`.
+    pub profiles: HashMap<String, Profile>,
+    /// The profiles used by default for playlists, by the names of the playlists.
+    pub playlists: HashMap<String, String>,
+    /// Options passed to mpv, by their names (e.g., `audio-device = "pulse"`).
+    pub mpv: BTreeMap<String, toml::Value>,
+}
+
+/// A named set of playback options (e.g., `focus` or `party`), selected with `--profile`.
+///
+/// Its settings take precedence over the settings of the config file, but not over the options
+/// given on the command line or with environment variables.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Profile {
+    pub show_video: Option<bool>,
+    pub only_video: Option<bool>,
+    pub volume: Option<u8>,
+    #[serde(rename = "loop")]
+    pub loop_audio: Option<bool>,
+    pub mute: Option<bool>,
+    pub shuffle: Option<bool>,
+    pub download: Option<bool>,
+    pub notification: Option<String>,
+    pub pick: Option<usize>,
+    pub menu: Option<String>,
+    pub source: Option<Vec<Source>>,
+    pub offline: Option<bool>,
+    pub fuzzy: Option<bool>,
+    pub quality: Option<Quality>,
+    pub audio_format: Option<AudioFormat>,
+    /// Options passed to mpv, by their names (e.g., `af = "loudnorm"`).
+    pub mpv: BTreeMap<String, toml::Value>,
+    /// Options passed to mpv in `key=value` form (e.g., `af=loudnorm`), after the options of the
+    /// `mpv` table.
+    pub mpv_options: Vec<String>,
+}
+
+impl Config {
+    /// Reads the configuration file.
+    ///
+    /// # Parameters
+    /// - `path`: The path of the file given by user. Defaults to `$XDG_CONFIG_HOME/playit/config.toml`.
+    ///
+    /// # Returns
+    /// - `Ok(Config)`: The configuration. Empty if the default file doesn't exist.
+    /// - `Err(String)`: An error message if the file couldn't be read or parsed, or the file
+    ///   given by user doesn't exist.
+    pub fn load(path: Option<&str>) -> Result<Self, String> {
+        let path = match path {
+            Some(path) => path.to_string(),
+            None => match default_path() {
+                Some(path) if fs::exists(&path).unwrap_or(false) => path,
+                _ => return Ok(Config::default()),
+            },
+        };
+        let content = fs::read_to_string(&path)
+            .map_err(|e| format!("Unable to read the config file {}: {}", path, e))?;
+        toml::from_str(&content)
+            .map_err(|e| format!("Unable to parse the config file {}: {}", path, e))
+    }
+
+    /// Finds the profile to use.
+    ///
+    /// The profile given by user is used first, then the default profile of the playlist, then
+    /// the `profile` setting of the config file. The built-in `data-saver` profile can be used
+    /// without defining it, unless the config file defines a profile with the same name.
+    ///
+    /// # Parameters
+    /// - `name`: The name of the profile given by user, if any.
+    /// - `playlist`: The name of the playlist which is used, if any.
+    ///
+    /// # Returns
+    /// - `Ok(Some((name, profile)))`: The name of the profile and its settings.
+    /// - `Ok(None)`: No profile is selected.
+    /// - `Err(String)`: An error message if the selected profile doesn't exist.
+    pub fn profile(
+        &self,
+        name: Option<&str>,
+        playlist: Option<&str>,
+    ) -> Result<Option<(String, Profile)>, String> {
+        let name = name
+            .or_else(|| self.playlists.get(playlist?).map(String::as_str))
+            .or(self.profile.as_deref());
+        let Some(name) = name else {
+            return Ok(None);
+        };
+        match self.profiles.get(name) {
+            Some(profile) => Ok(Some((name.to_string(), profile.clone()))),
+            None if name == DATA_SAVER => Ok(Some((name.to_string(), Profile::data_saver()))),
+            None => {
+                let mut names: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+                names.push(DATA_SAVER);
+                names.sort_unstable();
+                names.dedup();
+                Err(format!(
+                    "Profile '{}' is not defined in the config file. Available profiles: {}",
+                    name,
+                    names.join(", ")
+                ))
+            }
+        }
+    }
+}
+
+impl Profile {
+    /// Returns the built-in `data-saver` profile, which plays low quality audio without video.
+    fn data_saver() -> Self {
+        Profile {
+            show_video: Some(false),
+            only_video: Some(false),
+            quality: Some(Quality::Low),
+            audio_format: Some(AudioFormat::Opus),
+            ..Default::default()
+        }
+    }
+}
+
+/// Converts a table of mpv options in the configuration file to `key=value` options.
+///
+/// Booleans are converted to `yes` and `no`, as mpv expects them.
+///
+/// # Parameters
+/// - `table`: The mpv options by their names.
+///
+/// # Returns
+/// - `Ok(Vec<String>)`: The options in `key=value` form.
+/// - `Err(String)`: An error message if an option has an array or a table as its value.
+pub fn mpv_options(table: &BTreeMap<String, toml::Value>) -> Result<Vec<String>, String> {
+    table
+        .iter()
+        .map(|(key, value)| {
+            let value = match value {
+                toml::Value::String(value) => value.clone(),
+                toml::Value::Boolean(true) => String::from("yes"),
+                toml::Value::Boolean(false) => String::from("no"),
+                toml::Value::Integer(value) => value.to_string(),
+                toml::Value::Float(value) => value.to_string(),
+                _ => {
+                    return Err(format!(
+                        "The value of mpv option '{}' should be a string, number or boolean.",
+                        key
+                    ))
+                }
+            };
+            Ok(format!("{}={}", key, value))
+        })
+        .collect()
+}
+
+/// Returns the default path of the configuration file, if the configuration directory is known.
+pub fn default_path() -> Option<String> {
+    let dir = config_dir()?;
+    Some(CONFIG_FILE.replace("{}", &dir.display().to_string()))
+}
+
+/// Where the value of a setting comes from, in the order of precedence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+    /// Given on the command line.
+    Cli,
+    /// Given with an environment variable.
+    Env,
+    /// Read from the profile with the given name.
+    Profile(String),
+    /// Read from the configuration file.
+    Config,
+    /// The built-in default.
+    Default,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Origin::Cli => f.write_str("command line"),
+            Origin::Env => f.write_str("environment"),
+            Origin::Profile(name) => write!(f, "profile '{}'", name),
+            Origin::Config => f.write_str("config file"),
+            Origin::Default => f.write_str("default"),
+        }
+    }
+}
+
+/// The effective value of a setting, as shown by `config show`.
+///
+/// # Fields
+/// - `name`: The name of the setting in the configuration file.
+/// - `value`: The value in TOML syntax, or `None` if it isn't set.
+/// - `origin`: Where the value comes from.
+pub struct Setting {
+    pub name: &'static str,
+    pub value: Option<String>,
+    pub origin: Origin,
+}
+
+impl Setting {
+    /// Creates a setting, formatting its value in TOML syntax.
+    ///
+    /// # Parameters
+    /// - `name`: The name of the setting in the configuration file.
+    /// - `value`: The effective value. `None` values are shown as unset.
+    /// - `origin`: Where the value comes from.
+    pub fn new<T: Serialize>(name: &'static str, value: &T, origin: Origin) -> Self {
+        Setting {
+            name,
+            value: toml::Value::try_from(value)
+                .ok()
+                .map(|value| value.to_string()),
+            origin,
+        }
+    }
+}
+
+impl fmt::Display for Setting {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.value {
+            Some(ref value) => write!(f, "{} = {}  # {}", self.name, value, self.origin),
+            None => write!(f, "# {} is not set", self.name),
+        }
+    }
+}
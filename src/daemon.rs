@@ -0,0 +1,456 @@
+use crate::ipc::{self, MpvIpc};
+use crate::log::{error, info, warning};
+use crate::mpv::{Mpv, MpvArgs};
+use dirs::runtime_dir;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::time::Duration;
+
+/// Socket file used by the playit daemon.
+const DAEMON_SOCKET_FILE: &str = "{}/playit.sock";
+
+/// How long to wait for a client to send its request, so a stuck client doesn't block the
+/// daemon for the others.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Returns the default path of the daemon socket.
+///
+/// # Returns
+/// A string containing the path of the socket.
+pub fn socket_path() -> String {
+    let dir = runtime_dir().unwrap_or_else(std::env::temp_dir);
+    DAEMON_SOCKET_FILE.replace("{}", &dir.display().to_string())
+}
+
+/// Playback options sent with play requests, applied to the mpv instance of the daemon.
+///
+/// # Fields
+/// - `video`: Whether the video should be shown.
+/// - `audio`: Whether the audio should be played.
+/// - `volume`: The volume level (0-100).
+/// - `mute`: Whether the audio should be muted.
+/// - `loop_audio`: Whether the media should be looped.
+/// - `ytdl_format`: The `yt-dlp` format selector for the streams. The default of mpv is used if
+///   not set.
+/// - `mpv_options`: Other mpv options given by user (e.g., with `--mpv-opt`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaybackOptions {
+    pub video: bool,
+    pub audio: bool,
+    pub volume: u8,
+    pub mute: bool,
+    pub loop_audio: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ytdl_format: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mpv_options: MpvArgs,
+}
+
+/// Requests accepted by the daemon.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "request", rename_all = "kebab-case")]
+pub enum Request {
+    /// Replaces the queue with the given media and starts playing it.
+    Play {
+        items: Vec<String>,
+        options: PlaybackOptions,
+    },
+    /// Adds the media to the end of the queue, or right after the current media if `next` is set.
+    Enqueue { item: String, next: bool },
+    /// Returns the queue and the index of the current media.
+    List,
+    /// Removes the media at the given index (starts from 0) from the queue.
+    Remove { index: usize },
+    /// Clears the queue and stops the playback.
+    Clear,
+}
+
+/// Response sent by the daemon for every request.
+///
+/// # Fields
+/// - `ok`: Whether the request was handled successfully.
+/// - `message`: A message describing the result or the error.
+/// - `data`: Additional data returned by the request.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Response {
+    pub ok: bool,
+    pub message: String,
+    #[serde(default)]
+    pub data: Value,
+}
+
+impl Response {
+    fn success(message: &str) -> Self {
+        Response {
+            ok: true,
+            message: message.to_string(),
+            data: Value::Null,
+        }
+    }
+
+    fn with_data(message: &str, data: Value) -> Self {
+        Response {
+            ok: true,
+            message: message.to_string(),
+            data,
+        }
+    }
+
+    fn failure(message: &str) -> Self {
+        Response {
+            ok: false,
+            message: message.to_string(),
+            data: Value::Null,
+        }
+    }
+}
+
+/// Checks whether a daemon is listening on the given socket.
+///
+/// # Parameters
+/// - `socket`: The path of the daemon socket.
+///
+/// # Returns
+/// - `true` if a daemon accepted the connection, otherwise `false`.
+pub fn is_running(socket: &str) -> bool {
+    UnixStream::connect(socket).is_ok()
+}
+
+/// Sends a request to the daemon and waits for its response.
+///
+/// # Parameters
+/// - `socket`: The path of the daemon socket.
+/// - `request`: The request to send.
+///
+/// # Returns
+/// - `Ok(Response)`: The response if the daemon handled the request successfully.
+/// - `Err(String)`: An error message if the daemon is not running or the request failed.
+pub fn send(socket: &str, request: &Request) -> Result<Response, String> {
+    let mut stream = UnixStream::connect(socket)
+        .map_err(|e| format!("Unable to connect to playit daemon at {}: {}", socket, e))?;
+    let mut line = serde_json::to_string(request)
+        .map_err(|_| "Failed to convert request to JSON.".to_string())?;
+    line.push('\n');
+    stream
+        .write_all(line.as_bytes())
+        .map_err(|e| format!("Unable to send request to playit daemon: {}", e))?;
+
+    let mut buffer = String::new();
+    BufReader::new(stream)
+        .read_line(&mut buffer)
+        .map_err(|e| format!("Unable to read response from playit daemon: {}", e))?;
+    let response: Response = serde_json::from_str(&buffer)
+        .map_err(|_| "playit daemon sent an invalid response.".to_string())?;
+    if response.ok {
+        Ok(response)
+    } else {
+        Err(response.message)
+    }
+}
+
+/// Represents the daemon state: the mpv instance it owns and the play queue.
+///
+/// # Fields
+/// - `mpv`: The IPC connection to the mpv instance of the daemon.
+/// - `queue`: The media URLs in the queue, in the same order as the mpv playlist.
+/// - `changed_options`: The mpv options changed by the last play request, with their values
+///   before it.
+/// - `applied_profiles`: The mpv profiles applied by the last play request.
+struct Daemon {
+    mpv: MpvIpc,
+    queue: Vec<String>,
+    changed_options: HashMap<String, Value>,
+    applied_profiles: Vec<String>,
+}
+
+impl Daemon {
+    /// Handles a single request and builds the response for it.
+    fn handle(&mut self, request: Request) -> Result<Response, String> {
+        match request {
+            Request::Play { items, options } => {
+                if items.is_empty() {
+                    return Ok(Response::failure("Nothing to play."));
+                }
+                self.apply_options(&options)?;
+                let ignored = self.apply_mpv_options(&options.mpv_options);
+                for (index, item) in items.iter().enumerate() {
+                    let mode = if index == 0 { "replace" } else { "append" };
+                    self.mpv.command(json!(["loadfile", item, mode]))?;
+                }
+                self.mpv.set_property("pause", json!(false))?;
+                self.queue = items;
+                info(format!("Playing {} item(s).", self.queue.len()));
+                Ok(Response::with_data(
+                    "Playing.",
+                    json!({ "ignored_options": ignored }),
+                ))
+            }
+            Request::Enqueue { item, next } => {
+                let current = self.current_index();
+                self.mpv.command(json!(["loadfile", item, "append-play"]))?;
+                self.queue.push(item);
+
+                // Move the appended media right after the current one
+                let last = self.queue.len() - 1;
+                if let Some(target) = current.map(|index| index + 1).filter(|t| next && *t < last) {
+                    self.mpv.command(json!(["playlist-move", last, target]))?;
+                    let item = self.queue.remove(last);
+                    self.queue.insert(target, item);
+                    info(format!("Added media to the queue at: {}", target));
+                } else {
+                    info(format!("Added media to the end of the queue: {}", last));
+                }
+                Ok(Response::success("Added to the queue."))
+            }
+            Request::List => Ok(Response::with_data(
+                "Queue listed.",
+                json!({ "items": self.queue, "current": self.current_index() }),
+            )),
+            Request::Remove { index } => {
+                if index >= self.queue.len() {
+                    return Ok(Response::failure("There is no media at the given index."));
+                }
+                self.mpv.command(json!(["playlist-remove", index]))?;
+                let item = self.queue.remove(index);
+                info(format!("Removed media from the queue: {}", item));
+                Ok(Response::success("Removed from the queue."))
+            }
+            Request::Clear => {
+                // `stop` clears the whole playlist of mpv, including the current media
+                self.mpv.command(json!(["stop"]))?;
+                self.queue.clear();
+                info("Queue cleared.");
+                Ok(Response::success("Queue cleared."))
+            }
+        }
+    }
+
+    /// Returns the index of the media mpv is playing, if any.
+    fn current_index(&mut self) -> Option<usize> {
+        self.mpv
+            .get_property("playlist-pos")
+            .ok()
+            .and_then(|value| value.as_u64())
+            .map(|index| index as usize)
+    }
+
+    /// Applies the playback options of a request to mpv.
+    fn apply_options(&mut self, options: &PlaybackOptions) -> Result<(), String> {
+        let video = if options.video { "auto" } else { "no" };
+        let audio = if options.audio { "auto" } else { "no" };
+        let loop_file = if options.loop_audio { "inf" } else { "no" };
+        self.mpv.set_property("vid", json!(video))?;
+        self.mpv.set_property("aid", json!(audio))?;
+        self.mpv.set_property("volume", json!(options.volume))?;
+        self.mpv.set_property("mute", json!(options.mute))?;
+        self.mpv.set_property("loop-file", json!(loop_file))?;
+        let format = options.ytdl_format.as_deref().unwrap_or("");
+        self.mpv.set_property("ytdl-format", json!(format))?;
+        Ok(())
+    }
+
+    /// Applies the mpv options given by user to the running mpv, as if they were given on its
+    /// command line. The options of the previous request are reset first, so they only apply to
+    /// the media of their request.
+    ///
+    /// # Returns
+    /// The options which mpv couldn't apply at runtime (e.g., options which only work at
+    /// startup).
+    fn apply_mpv_options(&mut self, options: &MpvArgs) -> Vec<String> {
+        self.reset_mpv_options();
+        let mut ignored = Vec::new();
+        for (key, value) in options {
+            let name = key.trim_start_matches("--");
+            // Flags are the same as setting the option to `yes`, or `no` for `no-` flags
+            let (name, value) = match value {
+                Some(value) => (name, value.as_str()),
+                None => match name.strip_prefix("no-") {
+                    Some(name) => (name, "no"),
+                    None => (name, "yes"),
+                },
+            };
+            let result = match name {
+                "profile" => self
+                    .mpv
+                    .command(json!(["apply-profile", value]))
+                    .inspect(|_| self.applied_profiles.push(value.to_string())),
+                _ => {
+                    // Keep the value from before the first change, for resetting it later
+                    if !self.changed_options.contains_key(name) {
+                        if let Ok(previous) = self.mpv.get_property(&format!("options/{}", name)) {
+                            self.changed_options.insert(name.to_string(), previous);
+                        }
+                    }
+                    self.mpv.command(json!(["set", name, value]))
+                }
+            };
+            if let Err(e) = result {
+                warning(format!("Unable to apply mpv option '{}': {}", name, e));
+                ignored.push(format!("{}={}", name, value));
+            }
+        }
+        ignored
+    }
+
+    /// Resets the mpv options changed by the previous play request to their values from before
+    /// it, and restores the options changed by its profiles.
+    fn reset_mpv_options(&mut self) {
+        // Only the profiles with `profile-restore` can be restored, the others are left applied
+        for profile in self.applied_profiles.drain(..).rev() {
+            let _ = self
+                .mpv
+                .command(json!(["apply-profile", profile, "restore"]));
+        }
+        for (name, value) in self.changed_options.drain() {
+            if let Err(e) = self.mpv.set_property(&format!("options/{}", name), value) {
+                warning(format!("Unable to reset mpv option '{}': {}", name, e));
+            }
+        }
+    }
+}
+
+/// Runs the daemon in the foreground.
+///
+/// The daemon spawns a single idle mpv instance listening on `mpv_socket`, and accepts requests
+/// on `socket` until mpv exits.
+///
+/// # Parameters
+/// - `socket`: The path of the daemon socket.
+/// - `mpv_socket`: The path of the mpv IPC socket.
+/// - `mpv_args`: Arguments for the mpv instance of the daemon.
+/// - `mpris`: Whether to expose the mpv instance as a MPRIS player.
+/// - `mpris_bus`: Address of the D-Bus bus for MPRIS, instead of the session bus.
+/// - `on_failure`: Called with the URL or path of each media mpv couldn't play.
+///
+/// # Returns
+/// - `Err(String)` if the daemon couldn't be started. Otherwise it doesn't return.
+pub fn run(
+    socket: &str,
+    mpv_socket: &str,
+    mut mpv_args: MpvArgs,
+    mpris: bool,
+    mpris_bus: Option<&str>,
+    on_failure: impl Fn(&str) + Send + 'static,
+) -> Result<(), String> {
+    if is_running(socket) {
+        return Err(format!("playit daemon is already running at {}", socket));
+    }
+    // Remove the socket left by a daemon which didn't exit cleanly
+    if fs::exists(socket).unwrap_or(false) {
+        fs::remove_file(socket).map_err(|e| format!("Unable to remove stale socket: {}", e))?;
+    }
+    let listener =
+        UnixListener::bind(socket).map_err(|e| format!("Unable to listen on {}: {}", socket, e))?;
+
+    // mpv replaces the socket of an older instance, but remove it first so that instance is
+    // never mistaken for the new one while waiting below
+    let _ = fs::remove_file(mpv_socket);
+    mpv_args.push((
+        "--input-ipc-server".to_string(),
+        Some(mpv_socket.to_string()),
+    ));
+    info("Spawning mpv instance for the daemon.");
+    let mut child = Mpv::idle(Some(mpv_args)).spawn_child()?;
+    let mpv = MpvIpc::connect_waiting(mpv_socket)?;
+
+    if mpris {
+        start_mpris(mpv_socket, mpris_bus);
+    }
+
+    let watched_socket = mpv_socket.to_string();
+    std::thread::spawn(move || {
+        let result = ipc::watch_failures(&watched_socket, |media, e| {
+            warning(format!("mpv couldn't play '{}': {}", media, e));
+            on_failure(media);
+        });
+        if let Err(e) = result {
+            warning("Unable to watch the playback for media which can't be played.");
+            error(&e);
+        }
+    });
+
+    // Exit together with mpv, so the socket doesn't point to a daemon without player
+    let socket_file = socket.to_string();
+    std::thread::spawn(move || {
+        let _ = child.wait();
+        info("mpv exited, stopping the daemon.");
+        let _ = fs::remove_file(&socket_file);
+        std::process::exit(0);
+    });
+
+    let mut daemon = Daemon {
+        mpv,
+        queue: Vec::new(),
+        changed_options: HashMap::new(),
+        applied_profiles: Vec::new(),
+    };
+    info(format!("playit daemon is listening on: {}", socket));
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                if let Err(e) = serve(&mut daemon, stream) {
+                    warning("Unable to serve the request.");
+                    error(&e);
+                }
+            }
+            Err(e) => {
+                warning("Unable to accept connection.");
+                error(&e);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads a request from the connection, handles it and writes the response back.
+fn serve(daemon: &mut Daemon, stream: UnixStream) -> Result<(), String> {
+    stream
+        .set_read_timeout(Some(CLIENT_TIMEOUT))
+        .and_then(|_| stream.set_write_timeout(Some(CLIENT_TIMEOUT)))
+        .map_err(|e| format!("Unable to set timeout of the connection: {}", e))?;
+    let mut reader = BufReader::new(
+        stream
+            .try_clone()
+            .map_err(|e| format!("Unable to read request: {}", e))?,
+    );
+    let mut buffer = String::new();
+    let read = reader
+        .read_line(&mut buffer)
+        .map_err(|e| format!("Unable to read request: {}", e))?;
+    // Connections closed without a request are only checking if the daemon is running
+    if read == 0 {
+        return Ok(());
+    }
+
+    let response = match serde_json::from_str::<Request>(&buffer) {
+        Ok(request) => daemon
+            .handle(request)
+            .unwrap_or_else(|e| Response::failure(&e)),
+        Err(_) => Response::failure("Invalid request."),
+    };
+    let mut line = serde_json::to_string(&response)
+        .map_err(|_| "Failed to convert response to JSON.".to_string())?;
+    line.push('\n');
+    (&stream)
+        .write_all(line.as_bytes())
+        .map_err(|e| format!("Unable to send response: {}", e))
+}
+
+/// Starts the MPRIS service, only warning on errors since the daemon works without it.
+#[cfg(feature = "mpris")]
+fn start_mpris(mpv_socket: &str, mpris_bus: Option<&str>) {
+    if let Err(e) = crate::mpris::start(mpv_socket, mpris_bus) {
+        warning("Unable to start MPRIS service. The media keys will not work.");
+        error(&e);
+    }
+}
+
+/// Warns that MPRIS is not available in this build.
+#[cfg(not(feature = "mpris"))]
+fn start_mpris(_mpv_socket: &str, _mpris_bus: Option<&str>) {
+    warning("playit is built without MPRIS support. Use `--no-mpris` to hide this warning.");
+}
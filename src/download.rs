@@ -0,0 +1,147 @@
+use crate::log::info;
+use crate::quality::StreamFormat;
+use crate::youtube;
+use dirs::data_dir;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Directory path for storing downloaded media.
+const DOWNLOADS_DIR: &str = "{}/playit/downloads";
+
+/// Returns the directory of downloaded media, creating it if it doesn't exist.
+///
+/// # Returns
+/// - `Ok(PathBuf)`: The path of `$XDG_DATA_HOME/playit/downloads`.
+/// - `Err(String)`: An error message if the directory couldn't be determined or created.
+pub fn directory() -> Result<PathBuf, String> {
+    let dir = path().ok_or("Unable to retrieve the data directory".to_string())?;
+    if !dir.exists() {
+        fs::create_dir_all(&dir)
+            .map_err(|e| format!("Unable to create download directory: {}", e))?;
+        info(format!("Created download directory: {}", dir.display()));
+    }
+    Ok(dir)
+}
+
+/// Returns the path of the download directory, without creating it.
+fn path() -> Option<PathBuf> {
+    let data = data_dir()?;
+    Some(PathBuf::from(
+        DOWNLOADS_DIR.replace("{}", &data.display().to_string()),
+    ))
+}
+
+/// A downloaded media file.
+///
+/// # Fields
+/// - `path`: The path of the file.
+/// - `format`: The quality and the audio format the file was downloaded in.
+pub struct Download {
+    pub path: String,
+    pub format: StreamFormat,
+}
+
+/// Downloads the audio of a media URL into the download directory using `yt-dlp`.
+///
+/// The file is named after the URL and the format, so it can be found later without the
+/// network. If it was already downloaded in the same format, the existing file is used.
+/// Otherwise it is downloaded again, replacing the file in the other format.
+///
+/// # Parameters
+/// - `url`: The URL of the media.
+/// - `format`: The quality and the audio format to download.
+///
+/// # Returns
+/// - `Ok(Download)`: The downloaded file.
+/// - `Err(String)`: An error message if `yt-dlp` failed.
+pub fn download(url: &str, format: &StreamFormat) -> Result<Download, String> {
+    let existing = find(url);
+    if let Some(existing) = existing.as_ref() {
+        if existing.format.matches(format) {
+            return Ok(Download {
+                path: existing.path.clone(),
+                format: existing.format,
+            });
+        }
+        info(format!(
+            "'{}' was downloaded in another format ({}), downloading it again ({}).",
+            url,
+            existing.format.label(),
+            format.label()
+        ));
+    }
+    let dir = directory()?;
+    let template = dir.join(format!("{}.{}.%(ext)s", file_stem(url), format.tag()));
+    let output = Command::new(youtube::program())
+        .arg("--no-playlist") // Avoid playlist downloads
+        .arg("--quiet") // Suppress unnecessary output
+        .arg("--no-simulate") // Download the media even if something is printed
+        .arg("--format") // Prefer audio-only streams
+        .arg(format.audio_selector())
+        .arg("--output")
+        .arg(&template)
+        .arg("--print") // Print the path of the downloaded file
+        .arg("after_move:filepath")
+        .arg(url)
+        .output()
+        .map_err(|_| "Unable to create process for downloading media with 'yt-dlp'".to_string())?;
+
+    let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() || path.is_empty() {
+        return Err(format!(
+            "Unable to download '{}': {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    info(format!("Downloaded '{}' to: {}", url, path));
+    // Only one file is kept for every URL
+    if let Some(existing) = existing.filter(|existing| existing.path != path) {
+        let _ = fs::remove_file(existing.path);
+    }
+    Ok(Download {
+        path,
+        format: *format,
+    })
+}
+
+/// Finds the downloaded file of a media URL.
+///
+/// # Parameters
+/// - `url`: The URL of the media.
+///
+/// # Returns
+/// - `Some(Download)`: The downloaded file and its format. The files downloaded before the
+///   format was recorded have the best quality.
+/// - `None`: If the media is not downloaded.
+pub fn find(url: &str) -> Option<Download> {
+    let stem = file_stem(url);
+    fs::read_dir(path()?)
+        .ok()?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .find_map(|path| {
+            let name = path.file_stem()?.to_str()?;
+            let format = match name.split_once('.') {
+                Some((hash, tag)) if hash == stem => StreamFormat::from_tag(tag)?,
+                None if name == stem => StreamFormat::default(),
+                _ => return None,
+            };
+            Some(Download {
+                path: path.display().to_string(),
+                format,
+            })
+        })
+}
+
+/// Returns the name of the downloaded file of a URL (without the format and the extension).
+///
+/// The name is the FNV-1a hash of the URL, which stays the same between versions of playit.
+fn file_stem(url: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in url.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
@@ -0,0 +1,519 @@
+use crate::playlist::PlaylistItem;
+use crate::utils::{decode_uri, file_uri, file_uri_path, is_url, now};
+use clap::ValueEnum;
+use std::path::Path;
+
+/// Playlist formats which can be imported and exported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// M3U playlist with `#EXTINF` lines.
+    M3u,
+    /// UTF-8 M3U playlist with `#EXTINF` lines.
+    M3u8,
+    /// PLS playlist (version 2).
+    Pls,
+    /// XML Shareable Playlist Format.
+    Xspf,
+}
+
+impl Format {
+    /// Returns the file extension of the format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::M3u => "m3u",
+            Format::M3u8 => "m3u8",
+            Format::Pls => "pls",
+            Format::Xspf => "xspf",
+        }
+    }
+
+    /// Detects the format of a playlist file from its extension.
+    ///
+    /// # Parameters
+    /// - `path`: The path of the playlist file.
+    ///
+    /// # Returns
+    /// - `Some(Format)` if the extension is known, otherwise `None`.
+    pub fn detect(path: &str) -> Option<Format> {
+        let extension = Path::new(path).extension()?.to_str()?.to_lowercase();
+        Format::from_str(&extension, true).ok()
+    }
+}
+
+/// Converts playlist items into the given format.
+///
+/// # Parameters
+/// - `name`: The name of the playlist (used as the title of XSPF playlists).
+/// - `items`: The items of the playlist.
+/// - `format`: The format to export.
+///
+/// # Returns
+/// A string containing the playlist in the given format.
+pub fn export(name: &str, items: &[PlaylistItem], format: Format) -> String {
+    match format {
+        Format::M3u | Format::M3u8 => export_m3u(items),
+        Format::Pls => export_pls(items),
+        Format::Xspf => export_xspf(name, items),
+    }
+}
+
+/// Parses playlist items from the content of a playlist file.
+///
+/// Relative paths in the playlist are resolved from the directory of the playlist file.
+///
+/// # Parameters
+/// - `content`: The content of the playlist file.
+/// - `format`: The format of the playlist file.
+/// - `base`: The absolute directory of the playlist file.
+///
+/// # Returns
+/// - `Ok(Vec<PlaylistItem>)`: The items of the playlist.
+/// - `Err(String)`: An error message if the playlist has no items.
+pub fn import(content: &str, format: Format, base: &Path) -> Result<Vec<PlaylistItem>, String> {
+    let items: Vec<PlaylistItem> = match format {
+        Format::M3u | Format::M3u8 => import_m3u(content),
+        Format::Pls => import_pls(content),
+        Format::Xspf => import_xspf(content),
+    }
+    .into_iter()
+    .map(|(location, title, channel, duration)| {
+        let url = resolve_location(&location, base);
+        PlaylistItem {
+            query: url.clone(),
+            // Titles are exported as the location when unknown, don't take them as titles
+            title: title.filter(|title| *title != location),
+            url,
+            channel,
+            duration,
+            added_at: Some(now()),
+            tags: Vec::new(),
+            local_path: None,
+            local_format: None,
+        }
+    })
+    .collect();
+
+    if items.is_empty() {
+        return Err("The playlist file doesn't contain any media.".to_string());
+    }
+    Ok(items)
+}
+
+/// A media entry parsed from a playlist file: location, title, channel and duration.
+type Entry = (String, Option<String>, Option<String>, Option<f64>);
+
+fn export_m3u(items: &[PlaylistItem]) -> String {
+    let mut content = String::from("#EXTM3U\n");
+    for item in items {
+        let duration = item.duration.map(|d| d.round() as i64).unwrap_or(-1);
+        content.push_str(&format!("#EXTINF:{},{}\n", duration, item.name()));
+        if let Some(ref channel) = item.channel {
+            content.push_str(&format!("#EXTART:{}\n", channel));
+        }
+        content.push_str(&format!("{}\n", item.url));
+    }
+    content
+}
+
+fn import_m3u(content: &str) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let (mut title, mut channel, mut duration) = (None, None, None);
+    for line in content.lines().map(str::trim) {
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            // `#EXTINF:<duration>,<title>`, the duration is -1 when unknown
+            let (length, name) = info.split_once(',').unwrap_or((info, ""));
+            duration = length.trim().parse::<f64>().ok().filter(|d| *d >= 0.0);
+            title = Some(name.trim().to_string()).filter(|name| !name.is_empty());
+        } else if let Some(artist) = line.strip_prefix("#EXTART:") {
+            channel = Some(artist.trim().to_string()).filter(|artist| !artist.is_empty());
+        } else if !line.is_empty() && !line.starts_with('#') {
+            entries.push((
+                line.to_string(),
+                title.take(),
+                channel.take(),
+                duration.take(),
+            ));
+        }
+    }
+    entries
+}
+
+fn export_pls(items: &[PlaylistItem]) -> String {
+    let mut content = String::from("[playlist]\n");
+    for (index, item) in items.iter().enumerate() {
+        let number = index + 1;
+        let duration = item.duration.map(|d| d.round() as i64).unwrap_or(-1);
+        content.push_str(&format!("File{}={}\n", number, item.url));
+        content.push_str(&format!("Title{}={}\n", number, item.name()));
+        content.push_str(&format!("Length{}={}\n", number, duration));
+    }
+    content.push_str(&format!("NumberOfEntries={}\nVersion=2\n", items.len()));
+    content
+}
+
+fn import_pls(content: &str) -> Vec<Entry> {
+    // Entries are numbered, and their keys may come in any order
+    let mut entries: Vec<(usize, Entry)> = Vec::new();
+    for line in content.lines().map(str::trim) {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let key = key.trim().to_lowercase();
+        let value = value.trim().to_string();
+        let Some((field, number)) = ["file", "title", "length"].iter().find_map(|field| {
+            let number = key.strip_prefix(field)?.parse::<usize>().ok()?;
+            Some((*field, number))
+        }) else {
+            continue;
+        };
+
+        let position = match entries.iter().position(|(n, _)| *n == number) {
+            Some(position) => position,
+            None => {
+                entries.push((number, (String::new(), None, None, None)));
+                entries.len() - 1
+            }
+        };
+        let entry = &mut entries[position].1;
+        match field {
+            "file" => entry.0 = value,
+            "title" => entry.1 = Some(value).filter(|title| !title.is_empty()),
+            _ => entry.3 = value.parse::<f64>().ok().filter(|d| *d >= 0.0),
+        }
+    }
+    entries.sort_by_key(|(number, _)| *number);
+    entries
+        .into_iter()
+        .map(|(_, entry)| entry)
+        .filter(|entry| !entry.0.is_empty())
+        .collect()
+}
+
+fn export_xspf(name: &str, items: &[PlaylistItem]) -> String {
+    let mut content = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    content.push_str("<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n");
+    content.push_str(&format!("  <title>{}</title>\n", escape_xml(name)));
+    content.push_str("  <trackList>\n");
+    for item in items {
+        content.push_str("    <track>\n");
+        // XSPF locations are URIs, local paths are written as `file://` URIs
+        let location = if is_url(&item.url) {
+            item.url.clone()
+        } else {
+            file_uri(&item.url)
+        };
+        content.push_str(&format!(
+            "      <location>{}</location>\n",
+            escape_xml(&location)
+        ));
+        if let Some(ref title) = item.title {
+            content.push_str(&format!("      <title>{}</title>\n", escape_xml(title)));
+        }
+        if let Some(ref channel) = item.channel {
+            content.push_str(&format!(
+                "      <creator>{}</creator>\n",
+                escape_xml(channel)
+            ));
+        }
+        if let Some(duration) = item.duration {
+            // XSPF durations are in milliseconds
+            let milliseconds = (duration * 1000.0).round() as u64;
+            content.push_str(&format!("      <duration>{}</duration>\n", milliseconds));
+        }
+        content.push_str("    </track>\n");
+    }
+    content.push_str("  </trackList>\n</playlist>\n");
+    content
+}
+
+fn import_xspf(content: &str) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut rest = content;
+    while let Some((_, start)) = find_tag(rest, "track") {
+        let track = &rest[start..];
+        let Some(end) = track.find("</track>") else {
+            break;
+        };
+        let block = &track[..end];
+        rest = &track[end..];
+
+        if let Some(location) = xml_element(block, "location") {
+            // Relative locations are URI references, which may be percent-encoded too
+            let location = if is_url(&location) {
+                location
+            } else {
+                decode_uri(&location)
+            };
+            entries.push((
+                location,
+                xml_element(block, "title"),
+                xml_element(block, "creator"),
+                xml_element(block, "duration")
+                    .and_then(|d| d.parse::<f64>().ok())
+                    .map(|milliseconds| milliseconds / 1000.0),
+            ));
+        }
+    }
+    entries
+}
+
+/// Finds the first opening tag of an element with the given name, which may have attributes
+/// (e.g., `<track xml:id="1">`).
+///
+/// # Returns
+/// - `Some((start, end))` with the positions of the start and the end of the tag
+/// - `None` if there is no such tag
+fn find_tag(text: &str, name: &str) -> Option<(usize, usize)> {
+    let open = format!("<{}", name);
+    let mut offset = 0;
+    while let Some(position) = text[offset..].find(&open) {
+        let start = offset + position;
+        let after = &text[start + open.len()..];
+        if after.starts_with('>') || after.starts_with(char::is_whitespace) {
+            let end = start + open.len() + after.find('>')? + 1;
+            return Some((start, end));
+        }
+        offset = start + open.len();
+    }
+    None
+}
+
+/// Returns the unescaped text of the first element with the given name in a XML fragment.
+fn xml_element(block: &str, name: &str) -> Option<String> {
+    let close = format!("</{}>", name);
+    let (_, start) = find_tag(block, name)?;
+    let end = block[start..].find(&close)? + start;
+    Some(unescape_xml(block[start..end].trim())).filter(|text| !text.is_empty())
+}
+
+/// Escapes the special characters of XML.
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Replaces the XML entities with the characters they represent.
+fn unescape_xml(text: &str) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        result.push_str(&rest[..start]);
+        let entity = &rest[start..];
+        let Some(end) = entity.find(';') else {
+            rest = entity;
+            break;
+        };
+        let name = &entity[1..end];
+        let character = match name {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => name
+                .strip_prefix("#x")
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .or_else(|| name.strip_prefix('#')?.parse::<u32>().ok())
+                .and_then(char::from_u32),
+        };
+        match character {
+            Some(character) => {
+                result.push(character);
+                rest = &entity[end + 1..];
+            }
+            None => {
+                // Not an entity, keep the ampersand as it is
+                result.push('&');
+                rest = &entity[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Resolves a location of a playlist file: URLs are kept, `file://` URIs are turned into paths,
+/// and relative paths are joined with the directory of the playlist file.
+fn resolve_location(location: &str, base: &Path) -> String {
+    // Local files are saved with their paths, so they can be found by `--offline`
+    let path = file_uri_path(location);
+    let location = path.as_deref().unwrap_or(location);
+    if is_url(location) || Path::new(location).is_absolute() {
+        location.to_string()
+    } else {
+        base.join(location).display().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(
+        url: &str,
+        title: Option<&str>,
+        channel: Option<&str>,
+        duration: Option<f64>,
+    ) -> PlaylistItem {
+        PlaylistItem {
+            query: url.to_string(),
+            url: url.to_string(),
+            title: title.map(str::to_string),
+            channel: channel.map(str::to_string),
+            duration,
+            added_at: None,
+            tags: Vec::new(),
+            local_path: None,
+            local_format: None,
+        }
+    }
+
+    /// Returns the items of a playlist with the fields kept by the playlist formats.
+    fn items() -> Vec<PlaylistItem> {
+        vec![
+            item(
+                "https://www.youtube.com/watch?v=a&list=b",
+                Some("Rock & Roll <Live>"),
+                Some("Led Zeppelin"),
+                Some(245.0),
+            ),
+            item(
+                "/müzik/Barış Manço - Dağlar Dağlar.mp3",
+                Some("Dağlar Dağlar \"1976\""),
+                Some("Barış Manço"),
+                None,
+            ),
+            item("https://a/b", None, None, Some(61.0)),
+        ]
+    }
+
+    /// Exports the items and imports them back, keeping only the fields stored by the format.
+    fn round_trip(format: Format) -> Vec<Entry> {
+        let content = export("Mix & Match", &items(), format);
+        import(&content, format, Path::new("/base"))
+            .unwrap()
+            .into_iter()
+            .map(|item| {
+                assert_eq!(item.query, item.url);
+                (item.url, item.title, item.channel, item.duration)
+            })
+            .collect()
+    }
+
+    fn expected(channels: bool) -> Vec<Entry> {
+        items()
+            .into_iter()
+            .map(|item| {
+                let channel = item.channel.filter(|_| channels);
+                (item.url, item.title, channel, item.duration)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trips_m3u() {
+        assert_eq!(round_trip(Format::M3u8), expected(true));
+        assert_eq!(round_trip(Format::M3u), expected(true));
+    }
+
+    #[test]
+    fn round_trips_pls() {
+        assert_eq!(round_trip(Format::Pls), expected(false));
+    }
+
+    #[test]
+    fn round_trips_xspf() {
+        let content = export("Mix & Match", &items(), Format::Xspf);
+        assert!(content.contains("<title>Mix &amp; Match</title>"));
+        assert!(content.contains("<title>Rock &amp; Roll &lt;Live&gt;</title>"));
+        assert!(content.contains("?v=a&amp;list=b"));
+        assert!(
+            content.contains("<location>file:///m%C3%BCzik/Bar%C4%B1%C5%9F%20Man%C3%A7o%20-%20")
+        );
+        assert_eq!(round_trip(Format::Xspf), expected(true));
+    }
+
+    #[test]
+    fn imports_unknown_durations() {
+        let m3u = "#EXTM3U\n#EXTINF:-1,A\nhttps://a\n#EXTINF:12,\nhttps://b\nhttps://c\n";
+        let items = import(m3u, Format::M3u, Path::new("/")).unwrap();
+        let durations: Vec<_> = items.iter().map(|item| item.duration).collect();
+        assert_eq!(durations, [None, Some(12.0), None]);
+        assert_eq!(items[1].title, None);
+
+        let pls = "[playlist]\nFile1=https://a\nLength1=-1\nNumberOfEntries=1\n";
+        let items = import(pls, Format::Pls, Path::new("/")).unwrap();
+        assert_eq!(items[0].duration, None);
+    }
+
+    #[test]
+    fn imports_pls_keys_in_any_order() {
+        let pls = "[playlist]\nTitle2=Second\nlength1=5\nFile2=https://b\nNumberOfEntries=2\n\
+                   file1=https://a\nTitle1=First\nVersion=2\n";
+        let items = import(pls, Format::Pls, Path::new("/")).unwrap();
+        let items: Vec<_> = items
+            .into_iter()
+            .map(|item| (item.url, item.title, item.duration))
+            .collect();
+        assert_eq!(
+            items,
+            [
+                (
+                    String::from("https://a"),
+                    Some(String::from("First")),
+                    Some(5.0)
+                ),
+                (
+                    String::from("https://b"),
+                    Some(String::from("Second")),
+                    None
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn resolves_relative_locations() {
+        let base = Path::new("/home/me/music");
+        let urls = |content: &str, format: Format| -> Vec<String> {
+            let items = import(content, format, base).unwrap();
+            items.into_iter().map(|item| item.url).collect()
+        };
+        assert_eq!(
+            urls(
+                "a.mp3\n../b.mp3\n/c.mp3\nfile:///d%20e.mp3\nhttps://f\n",
+                Format::M3u
+            ),
+            [
+                "/home/me/music/a.mp3",
+                "/home/me/music/../b.mp3",
+                "/c.mp3",
+                "/d e.mp3",
+                "https://f"
+            ]
+        );
+        let xspf = "<playlist><trackList>\
+                    <track><location>sub/a%20b.mp3</location></track>\
+                    <track xml:id=\"2\">\n<location>file://localhost/c.mp3</location></track>\
+                    <trackList/></trackList></playlist>";
+        assert_eq!(
+            urls(xspf, Format::Xspf),
+            ["/home/me/music/sub/a b.mp3", "/c.mp3"]
+        );
+    }
+
+    #[test]
+    fn matches_tags_with_attributes() {
+        assert_eq!(find_tag("<tracks><track>", "track"), Some((8, 15)));
+        assert_eq!(find_tag("<track\tid=\"1\">", "track"), Some((0, 14)));
+        assert_eq!(find_tag("<trackList>", "track"), None);
+        assert_eq!(
+            xml_element("<title lang=\"tr\"> Çay &#x26; Simit </title>", "title"),
+            Some(String::from("Çay & Simit"))
+        );
+        assert_eq!(xml_element("<title></title>", "title"), None);
+    }
+}
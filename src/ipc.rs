@@ -0,0 +1,317 @@
+use dirs::runtime_dir;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+/// Socket file used by mpv instances spawned by playit.
+const SOCKET_FILE: &str = "{}/playit-mpv.sock";
+
+/// How long to wait for mpv to answer a command before giving up.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait for mpv to create its IPC socket after spawning.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Returns the default path of the mpv IPC socket.
+///
+/// The socket is placed inside `$XDG_RUNTIME_DIR` when it is available, otherwise inside the
+/// temporary directory of the system.
+///
+/// # Returns
+/// A string containing the path of the socket.
+pub fn socket_path() -> String {
+    let dir = runtime_dir().unwrap_or_else(std::env::temp_dir);
+    SOCKET_FILE.replace("{}", &dir.display().to_string())
+}
+
+/// Represents the state of the running playback.
+///
+/// # Fields
+/// - `title`: The title of the current media.
+/// - `url`: The URL or file path of the current media.
+/// - `position`: The current position in seconds.
+/// - `duration`: The duration of the current media in seconds (unknown for live streams).
+/// - `volume`: The volume level of mpv.
+/// - `paused`: Whether the playback is paused.
+/// - `playlist_index`: The index of the current media in the playlist (starts from 0).
+/// - `playlist_count`: The count of media in the playlist.
+#[derive(Debug, Serialize)]
+pub struct Status {
+    pub title: Option<String>,
+    pub url: Option<String>,
+    pub position: Option<f64>,
+    pub duration: Option<f64>,
+    pub volume: Option<f64>,
+    pub paused: bool,
+    pub playlist_index: Option<i64>,
+    pub playlist_count: Option<i64>,
+}
+
+/// Represents a connection to the JSON IPC socket of a running mpv instance.
+///
+/// # Fields
+/// - `reader`: Buffered reader for the responses and events sent by mpv.
+/// - `writer`: The socket used for sending commands to mpv.
+/// - `request_id`: The id of the last request, used for matching responses with requests.
+pub struct MpvIpc {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+    request_id: u64,
+}
+
+impl MpvIpc {
+    /// Connects to the mpv IPC socket at the given path.
+    ///
+    /// # Parameters
+    /// - `path`: The path of the socket passed to mpv with `--input-ipc-server`.
+    ///
+    /// # Returns
+    /// - `Ok(MpvIpc)` if the connection was established.
+    /// - `Err(String)` if there is no mpv instance listening on the socket.
+    pub fn connect(path: &str) -> Result<Self, String> {
+        let writer = UnixStream::connect(path)
+            .map_err(|e| format!("Unable to connect to mpv socket at {}: {}", path, e))?;
+        writer
+            .set_read_timeout(Some(RESPONSE_TIMEOUT))
+            .map_err(|e| format!("Unable to configure mpv socket: {}", e))?;
+        let reader = writer
+            .try_clone()
+            .map_err(|e| format!("Unable to configure mpv socket: {}", e))?;
+        Ok(MpvIpc {
+            reader: BufReader::new(reader),
+            writer,
+            request_id: 0,
+        })
+    }
+
+    /// Connects to the mpv IPC socket at the given path, waiting for a starting mpv to create it.
+    ///
+    /// # Parameters
+    /// - `path`: The path of the socket passed to mpv with `--input-ipc-server`.
+    ///
+    /// # Returns
+    /// - `Ok(MpvIpc)` if the connection was established.
+    /// - `Err(String)` if mpv didn't create the socket in time.
+    pub fn connect_waiting(path: &str) -> Result<Self, String> {
+        let started = std::time::Instant::now();
+        loop {
+            match MpvIpc::connect(path) {
+                Ok(mpv) => return Ok(mpv),
+                Err(e) if started.elapsed() > STARTUP_TIMEOUT => return Err(e),
+                Err(_) => std::thread::sleep(Duration::from_millis(100)),
+            }
+        }
+    }
+
+    /// Sends a command to mpv and waits for its response.
+    ///
+    /// Events which are sent by mpv while waiting are skipped.
+    ///
+    /// # Parameters
+    /// - `args`: The command and its arguments (e.g., `["seek", 10, "relative"]`).
+    ///
+    /// # Returns
+    /// - `Ok(Value)`: The `data` field of the response (`null` if the command has no result).
+    /// - `Err(String)`: An error message if the command failed or mpv didn't answer.
+    pub fn command(&mut self, args: Value) -> Result<Value, String> {
+        self.request_id += 1;
+        let request = json!({ "command": args, "request_id": self.request_id });
+        let mut line = request.to_string();
+        line.push('\n');
+        self.writer
+            .write_all(line.as_bytes())
+            .map_err(|e| format!("Unable to send command to mpv: {}", e))?;
+
+        loop {
+            let mut buffer = String::new();
+            let read = self
+                .reader
+                .read_line(&mut buffer)
+                .map_err(|e| format!("Unable to read response from mpv: {}", e))?;
+            if read == 0 {
+                return Err("mpv closed the IPC connection.".to_string());
+            }
+            let response: Value = match serde_json::from_str(&buffer) {
+                Ok(response) => response,
+                Err(_) => continue,
+            };
+            // Skip events and responses of other requests
+            if response.get("request_id").and_then(Value::as_u64) != Some(self.request_id) {
+                continue;
+            }
+            return match response.get("error").and_then(Value::as_str) {
+                Some("success") => Ok(response.get("data").cloned().unwrap_or(Value::Null)),
+                Some(e) => Err(format!("mpv rejected the command: {}", e)),
+                None => Err("mpv sent an invalid response.".to_string()),
+            };
+        }
+    }
+
+    /// Reads a property of the running mpv instance.
+    ///
+    /// # Parameters
+    /// - `name`: The name of the property (e.g., `pause`, `volume`).
+    ///
+    /// # Returns
+    /// - `Ok(Value)`: The value of the property.
+    /// - `Err(String)`: An error message if the property is not available.
+    pub fn get_property(&mut self, name: &str) -> Result<Value, String> {
+        self.command(json!(["get_property", name]))
+    }
+
+    /// Changes a property of the running mpv instance.
+    ///
+    /// # Parameters
+    /// - `name`: The name of the property (e.g., `pause`, `volume`).
+    /// - `value`: The new value of the property.
+    ///
+    /// # Returns
+    /// - `Ok(())` if the property was changed.
+    /// - `Err(String)` if mpv rejected the change.
+    pub fn set_property(&mut self, name: &str, value: Value) -> Result<(), String> {
+        self.command(json!(["set_property", name, value]))
+            .map(|_| ())
+    }
+
+    /// Asks mpv to send an event whenever the given property changes.
+    ///
+    /// # Parameters
+    /// - `id`: An id for identifying the events of this property.
+    /// - `name`: The name of the property (e.g., `pause`, `volume`).
+    ///
+    /// # Returns
+    /// - `Ok(())` if mpv started observing the property.
+    /// - `Err(String)` if mpv rejected the command.
+    pub fn observe_property(&mut self, id: u64, name: &str) -> Result<(), String> {
+        self.command(json!(["observe_property", id, name]))
+            .map(|_| ())
+    }
+
+    /// Waits for the next event sent by mpv.
+    ///
+    /// Note that the connection times out after a few seconds by default; use `set_timeout` on
+    /// connections which are used for waiting events.
+    ///
+    /// # Returns
+    /// - `Ok(Value)`: The event (e.g., `{"event": "property-change", "name": "pause", ...}`).
+    /// - `Err(String)`: An error message if the connection was closed.
+    pub fn next_event(&mut self) -> Result<Value, String> {
+        loop {
+            let mut buffer = String::new();
+            let read = self
+                .reader
+                .read_line(&mut buffer)
+                .map_err(|e| format!("Unable to read event from mpv: {}", e))?;
+            if read == 0 {
+                return Err("mpv closed the IPC connection.".to_string());
+            }
+            if let Ok(event) = serde_json::from_str::<Value>(&buffer) {
+                if event.get("event").is_some() {
+                    return Ok(event);
+                }
+            }
+        }
+    }
+
+    /// Changes how long to wait for mpv before giving up.
+    ///
+    /// # Parameters
+    /// - `timeout`: The new timeout, or `None` for waiting forever.
+    ///
+    /// # Returns
+    /// - `Ok(())` if the timeout was changed.
+    /// - `Err(String)` if the socket couldn't be configured.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<(), String> {
+        self.writer
+            .set_read_timeout(timeout)
+            .map_err(|e| format!("Unable to configure mpv socket: {}", e))
+    }
+
+    /// Queries the state of the running playback.
+    ///
+    /// Properties which are not available (e.g., duration of a live stream) are left empty.
+    ///
+    /// # Returns
+    /// - `Ok(Status)`: The state of the playback.
+    /// - `Err(String)`: An error message if mpv didn't answer.
+    pub fn status(&mut self) -> Result<Status, String> {
+        // Check the connection first, so the unavailable properties can be ignored below
+        let paused = self.get_property("pause")?.as_bool().unwrap_or(false);
+        Ok(Status {
+            title: self.optional_property("media-title").and_then(as_string),
+            url: self.optional_property("path").and_then(as_string),
+            position: self.optional_property("time-pos").and_then(|v| v.as_f64()),
+            duration: self.optional_property("duration").and_then(|v| v.as_f64()),
+            volume: self.optional_property("volume").and_then(|v| v.as_f64()),
+            paused,
+            playlist_index: self
+                .optional_property("playlist-pos")
+                .and_then(|v| v.as_i64())
+                .filter(|index| *index >= 0),
+            playlist_count: self
+                .optional_property("playlist-count")
+                .and_then(|v| v.as_i64()),
+        })
+    }
+
+    /// Reads a property, returning `None` if it is not available.
+    fn optional_property(&mut self, name: &str) -> Option<Value> {
+        self.get_property(name)
+            .ok()
+            .filter(|value| !value.is_null())
+    }
+}
+
+/// Converts a JSON string value into an owned string.
+fn as_string(value: Value) -> Option<String> {
+    value.as_str().map(str::to_string)
+}
+
+/// Watches the playback of mpv until it exits, and reports the media which mpv couldn't play
+/// (e.g., a deleted video which yt-dlp can't stream).
+///
+/// The playlist of mpv is observed from the start, because mpv may exit right after reporting
+/// the failure (e.g., when it played a single media), before the playlist can be queried.
+///
+/// # Parameters
+/// - `path`: The path of the mpv IPC socket.
+/// - `on_failure`: Called with the URL or path of each media mpv couldn't play, and the error
+///   of mpv.
+///
+/// # Returns
+/// - `Ok(())` when mpv exited.
+/// - `Err(String)` if mpv couldn't be reached.
+pub fn watch_failures(path: &str, mut on_failure: impl FnMut(&str, &str)) -> Result<(), String> {
+    let mut mpv = MpvIpc::connect_waiting(path)?;
+    mpv.observe_property(1, "playlist")?;
+    mpv.set_timeout(None)?;
+    // The media of the playlist entries, by their ids
+    let mut media: HashMap<i64, String> = HashMap::new();
+    while let Ok(event) = mpv.next_event() {
+        match event["event"].as_str() {
+            Some("property-change") if event["name"] == "playlist" => {
+                for entry in event["data"].as_array().into_iter().flatten() {
+                    if let (Some(id), Some(filename)) =
+                        (entry["id"].as_i64(), entry["filename"].as_str())
+                    {
+                        media.insert(id, filename.to_string());
+                    }
+                }
+            }
+            Some("end-file") if event["reason"] == "error" => {
+                let entry = event["playlist_entry_id"].as_i64();
+                if let Some(filename) = entry.and_then(|id| media.get(&id)) {
+                    on_failure(
+                        filename,
+                        event["file_error"].as_str().unwrap_or("unknown error"),
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
@@ -1,16 +1,31 @@
 pub mod cache;
+pub mod config;
+pub mod daemon;
+pub mod download;
+pub mod formats;
+pub mod ipc;
 pub mod log;
+pub mod media;
+#[cfg(feature = "mpris")]
+pub mod mpris;
 pub mod mpv;
 pub mod notification;
+pub mod picker;
 pub mod playlist;
+pub mod quality;
+pub mod search;
+pub mod storage;
 pub mod utils;
 pub mod youtube;
 use crate::log::*;
-use crate::notification::send_notification;
+use crate::media::MediaRef;
+use crate::notification::{format_notification, send_notification};
 use crate::playlist::Playlist;
-use clap::{ArgGroup, Parser};
+use crate::youtube::SearchResult;
+use clap::parser::ValueSource;
+use clap::{ArgGroup, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
 use rand::seq::SliceRandom;
-use std::collections::HashMap;
+use serde_json::json;
 
 #[derive(Debug, Parser)]
 #[clap(
@@ -22,10 +37,17 @@ use std::collections::HashMap;
     group = ArgGroup::new("play_options").required(false).args(&["play", "playlist"])
 )]
 pub struct Cli {
-    /// The URL or search query to play.
+    /// Run a command (e.g., control the playback, manage playlists) instead of playing media.
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// The URL, local path or search query to play.
     ///
     /// If a valid URL is provided, it will play the media from that URL.
-    /// If a search query is provided, it will attempt to find the media using YouTube search.
+    /// If a local file or directory (or a `file://` URI) is provided, it will play the file, or
+    /// all the audio files in the directory and its subdirectories.
+    /// If a search query is provided, it will attempt to find the media using the search sources
+    /// (YouTube by default, see `--source`).
     #[clap(long, short = 'p')]
     play: Option<String>,
 
@@ -33,16 +55,35 @@ pub struct Cli {
     ///
     /// Set to `true` (pass this option to arguments) to open the video in a window.
     /// Set to `false` (default) to play in the background without opening a window.
-    #[clap(long, short = 'w', default_value_t = false)]
+    #[clap(
+        long,
+        short = 'w',
+        env = "PLAYIT_SHOW_VIDEO",
+        default_value_t = false,
+        overrides_with = "no_show_video"
+    )]
     show_video: bool,
 
+    /// Don't show the video, even if the config file or the profile shows it.
+    #[clap(long = "no-show-video", overrides_with = "show_video")]
+    no_show_video: bool,
+
     /// Flag to play only the video (without audio).
     ///
     /// Set to `true` (pass this option to arguments) to play only the video, and the audio will be muted.
     /// Set to `false` (default) to play both audio and video.
-    #[clap(long, default_value_t = false)]
+    #[clap(
+        long,
+        env = "PLAYIT_ONLY_VIDEO",
+        default_value_t = false,
+        overrides_with = "no_only_video"
+    )]
     only_video: bool,
 
+    /// Play the audio too, even if the config file or the profile plays only the video.
+    #[clap(long = "no-only-video", overrides_with = "only_video")]
+    no_only_video: bool,
+
     /// The name of a playlist to play or modify.
     ///
     /// If specified, it will attempt to load the playlist and play it.
@@ -56,15 +97,22 @@ pub struct Cli {
     /// `<prefix>/<playlist>`.
     /// Also note that, all the playlist files should end with '.pl' extension and should be in
     /// JSON format.
-    #[clap(long)]
+    #[clap(long, global = true, env = "PLAYIT_PREFIX")]
     prefix: Option<String>,
 
     /// (PLAYLIST ONLY) Add a new media item to the selected playlist.
     ///
-    /// This option accepts a query (e.g., a song name or URL) to add a new media item to the playlist.
+    /// This option accepts a query (e.g., a song name, URL or local path) to add a new media item
+    /// to the playlist. Directories are added with all of their audio files.
     #[clap(long, short = 'a')]
     add: Option<String>,
 
+    /// (PLAYLIST ONLY) Tag the media item added with `--add`.
+    ///
+    /// This option can be given more than once for adding multiple tags.
+    #[clap(long = "tag", requires = "add")]
+    tags: Vec<String>,
+
     /// (PLAYLIST ONLY) Remove a media item from the selected playlist based on a query.
     ///
     /// This option accepts a query to find and remove a specific media item from the playlist.
@@ -80,150 +128,562 @@ pub struct Cli {
     /// (PLAYLIST ONLY) Shuffle the playlist items.
     ///
     /// This option will randomize the order of the media items in the playlist.
-    #[clap(long, short = 's', default_value_t = false)]
+    #[clap(
+        long,
+        short = 's',
+        env = "PLAYIT_SHUFFLE",
+        default_value_t = false,
+        overrides_with = "no_shuffle"
+    )]
     shuffle: bool,
 
+    /// Don't shuffle the playlist, even if the config file or the profile shuffles it.
+    #[clap(long = "no-shuffle", overrides_with = "shuffle")]
+    no_shuffle: bool,
+
     /// Set the volume for MPV playback.
     ///
     /// This option accepts an integer value to set the volume level (0-100). Default is 100.
-    #[clap(long, short = 'v', default_value_t = 100)]
+    #[clap(long, short = 'v', env = "PLAYIT_VOLUME", default_value_t = 100)]
     volume: u8,
 
     /// Loop the audio or playlist when it finishes.
     ///
     /// This option will repeat the audio once it is finished.
-    #[clap(long = "loop", default_value_t = false)]
+    #[clap(
+        long = "loop",
+        env = "PLAYIT_LOOP",
+        default_value_t = false,
+        overrides_with = "no_loop_audio"
+    )]
     loop_audio: bool,
 
+    /// Don't loop, even if the config file or the profile loops.
+    #[clap(long = "no-loop", overrides_with = "loop_audio")]
+    no_loop_audio: bool,
+
     /// Mute the audio during playback.
     ///
     /// This option will mute the audio while the media is playing.
-    #[clap(long, short = 'm', default_value_t = false)]
+    #[clap(
+        long,
+        short = 'm',
+        env = "PLAYIT_MUTE",
+        default_value_t = false,
+        overrides_with = "no_mute"
+    )]
     mute: bool,
 
+    /// Don't mute the audio, even if the config file or the profile mutes it.
+    #[clap(long = "no-mute", overrides_with = "mute")]
+    no_mute: bool,
+
     /// Send a notification when audio starts playing.
     ///
     /// This option will send a notification when the media started playing.
     /// You can pass "" to not use notifications.
-    /// The '{}' will be replaced with media url.
-    #[clap(long, short = 'n', default_value_t = String::from("Now playing: {}"))]
+    /// The '{title}', '{uploader}' and '{duration}' will be replaced with the metadata of media
+    /// (the title falls back to the URL when unknown), and '{}' or '{url}' with media url.
+    #[clap(
+        long,
+        short = 'n',
+        env = "PLAYIT_NOTIFICATION",
+        default_value_t = String::from("Now playing: {title}")
+    )]
     notification: String,
+
+    /// Path of the mpv IPC socket.
+    ///
+    /// Every mpv instance started by playit listens on this socket, and the control commands
+    /// (`pause`, `next`, `stop`...) are sent to it. Defaults to `$XDG_RUNTIME_DIR/playit-mpv.sock`.
+    #[clap(long, global = true, env = "PLAYIT_SOCKET")]
+    socket: Option<String>,
+
+    /// Path of the playit daemon socket.
+    ///
+    /// When a daemon is listening on this socket, `--play` and `--play-playlist` send the media
+    /// to it instead of spawning a new mpv. Defaults to `$XDG_RUNTIME_DIR/playit.sock`.
+    #[clap(long, global = true, env = "PLAYIT_DAEMON_SOCKET")]
+    daemon_socket: Option<String>,
+
+    /// Choose from the top N search results instead of playing the first one.
+    ///
+    /// The results are listed with their titles, channels, durations and view counts. The chosen
+    /// result is cached for the query, so the next searches for it will play the same media.
+    #[clap(long, global = true, env = "PLAYIT_PICK", value_name = "N")]
+    pick: Option<usize>,
+
+    /// Menu command for choosing a search result with `--pick` or `--fuzzy` (e.g., `dmenu`,
+    /// `rofi -dmenu`, `fzf`).
+    ///
+    /// The results are written to the standard input of the command, one per line, and the
+    /// line it prints is taken as the choice. Without it, the choice is asked on the terminal.
+    #[clap(long, global = true, env = "PLAYIT_MENU", value_name = "CMD")]
+    menu: Option<String>,
+
+    /// The sources to search queries on, in order.
+    ///
+    /// When a source fails or finds nothing, the next one is searched. Multiple sources can be
+    /// given separated with commas (e.g., `--source ytm,yt`).
+    #[clap(
+        long,
+        global = true,
+        env = "PLAYIT_SOURCE",
+        value_enum,
+        value_delimiter = ',',
+        default_value = "youtube"
+    )]
+    source: Vec<search::Source>,
+
+    /// The directory searched by the `local` source. Defaults to `$XDG_MUSIC_DIR`.
+    #[clap(long, global = true, env = "PLAYIT_MUSIC_DIR", value_name = "DIR")]
+    music_dir: Option<String>,
+
+    /// Download the audio of the media given with `--play` before playing it.
+    ///
+    /// The file is stored in `$XDG_DATA_HOME/playit/downloads` and played instead of the URL
+    /// from then on, so it can be played with `--offline` later.
+    #[clap(
+        long,
+        default_value_t = false,
+        requires = "play",
+        conflicts_with = "offline",
+        overrides_with = "no_download"
+    )]
+    download: bool,
+
+    /// Don't download the media, even if the config file or the profile downloads it.
+    #[clap(long = "no-download", overrides_with = "download")]
+    no_download: bool,
+
+    /// Never use the network. Only the downloaded media and local files are played.
+    ///
+    /// Queries are looked up in the cache, or searched with the `local` source if it is given
+    /// with `--source`. The playlist items which are not downloaded are skipped.
+    #[clap(
+        long,
+        global = true,
+        env = "PLAYIT_OFFLINE",
+        default_value_t = false,
+        overrides_with = "no_offline"
+    )]
+    offline: bool,
+
+    /// Use the network, even if the config file or the profile is offline.
+    #[clap(long = "no-offline", global = true, overrides_with = "offline")]
+    no_offline: bool,
+
+    /// Days after which the cached search results expire and the queries are searched again.
+    ///
+    /// Pass 0 to never expire them. Expired results are still used with `--offline`.
+    #[clap(
+        long,
+        global = true,
+        env = "PLAYIT_CACHE_TTL",
+        value_name = "DAYS",
+        default_value_t = cache::DEFAULT_TTL_DAYS
+    )]
+    cache_ttl: u64,
+
+    /// Offer the cached results of similar queries before searching a query which is not cached.
+    ///
+    /// The choice is made on the terminal, or with the command given with `--menu`.
+    #[clap(
+        long,
+        global = true,
+        env = "PLAYIT_FUZZY",
+        default_value_t = false,
+        overrides_with = "no_fuzzy"
+    )]
+    fuzzy: bool,
+
+    /// Don't offer the results of similar queries, even if the config file or the profile does.
+    #[clap(long = "no-fuzzy", global = true, overrides_with = "fuzzy")]
+    no_fuzzy: bool,
+
+    /// Path of the mpv program. Defaults to `mpv` from `$PATH`.
+    #[clap(long, global = true, env = "PLAYIT_MPV_PATH", value_name = "PATH")]
+    mpv_path: Option<String>,
+
+    /// Path of the yt-dlp program, also used by mpv for streaming. Defaults to `yt-dlp` from
+    /// `$PATH`.
+    #[clap(long, global = true, env = "PLAYIT_YTDLP_PATH", value_name = "PATH")]
+    ytdlp_path: Option<String>,
+
+    /// Path of the cache database. Defaults to `$XDG_CACHE_HOME/playit.db`.
+    #[clap(long, global = true, env = "PLAYIT_CACHE_PATH", value_name = "PATH")]
+    cache_path: Option<String>,
+
+    /// Path of the configuration file. Defaults to `$XDG_CONFIG_HOME/playit/config.toml`.
+    ///
+    /// The file sets the defaults of the options, which are used when the options are not given
+    /// on the command line or with their environment variables (e.g., `PLAYIT_VOLUME`).
+    #[clap(long, global = true, env = "PLAYIT_CONFIG", value_name = "FILE")]
+    config: Option<String>,
+
+    /// The profile to use, defined in the config file (e.g., `focus`).
+    ///
+    /// A profile is a named set of options (e.g., volume, loop, shuffle) and raw mpv options.
+    /// Playlists can have a default profile in the `[playlists]` section of the config file.
+    #[clap(long, global = true, env = "PLAYIT_PROFILE", value_name = "NAME")]
+    profile: Option<String>,
+
+    /// The quality of the streams: `best`, `good`, `low` or the highest audio bitrate in kbps.
+    ///
+    /// `good` limits the audio to 160 kbps and the video to 720p, `low` limits them to 64 kbps
+    /// and 360p. The same quality is used for downloading media.
+    /// Without video, only the audio streams are fetched whatever the quality is.
+    #[clap(long, global = true, env = "PLAYIT_QUALITY", value_name = "QUALITY")]
+    quality: Option<quality::Quality>,
+
+    /// The preferred audio format of the streams and of the downloaded media.
+    ///
+    /// Other formats are used when the media isn't available in this format.
+    #[clap(long, global = true, env = "PLAYIT_AUDIO_FORMAT", value_enum)]
+    audio_format: Option<quality::AudioFormat>,
+
+    /// Pass an option to mpv (e.g., `--mpv-opt audio-device=pulse/sink`, `--mpv-opt profile=fast`).
+    ///
+    /// This option can be given more than once. The options which playit sets itself (e.g.,
+    /// `volume` or `loop`) are rejected, use the options of playit for them instead.
+    /// The options in the `[mpv]` section of the config file and in the selected profile are
+    /// passed before these.
+    #[clap(long = "mpv-opt", global = true, value_name = "KEY=VALUE")]
+    mpv_options: Vec<String>,
+
+    /// The mpv options of the config file, the profile and the command line, in this order.
+    #[clap(skip)]
+    mpv_args: mpv::MpvArgs,
+}
+
+/// Commands for controlling the playback and managing the data of playit.
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Pause the playback.
+    Pause,
+    /// Resume the paused playback.
+    Resume,
+    /// Toggle between paused and playing.
+    Toggle,
+    /// Skip to the next item of the playlist.
+    Next,
+    /// Go back to the previous item of the playlist.
+    Prev,
+    /// Seek in the current media.
+    Seek {
+        /// Amount of seconds to seek. Negative values seek backwards.
+        #[clap(allow_negative_numbers = true)]
+        seconds: f64,
+
+        /// Seek to the given position instead of seeking relative to the current one.
+        #[clap(long, default_value_t = false)]
+        absolute: bool,
+    },
+    /// Stop the playback and quit mpv.
+    Stop,
+    /// Run a daemon which owns a single mpv instance and the play queue.
+    ///
+    /// While the daemon is running, `--play` and `--playlist` send their media to it.
+    Daemon {
+        /// Don't expose the daemon as a MPRIS player over D-Bus.
+        #[clap(long, default_value_t = false)]
+        no_mpris: bool,
+
+        /// Address of the D-Bus bus for the MPRIS service, instead of the session bus.
+        ///
+        /// Useful for running the service on a private bus (e.g., `unix:path=/tmp/bus`).
+        #[clap(long)]
+        mpris_bus: Option<String>,
+    },
+    /// Manage the play queue of the daemon.
+    Queue {
+        #[clap(subcommand)]
+        command: QueueCommand,
+    },
+    /// List all playlists with their item counts and total durations.
+    Playlists,
+    /// Inspect and manage playlists.
+    Playlist {
+        #[clap(subcommand)]
+        command: PlaylistCommand,
+    },
+    /// Inspect and manage the cached search results.
+    Cache {
+        #[clap(subcommand)]
+        command: CacheCommand,
+    },
+    /// Inspect the configuration of playit.
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Print what is currently playing.
+    Status {
+        /// Print the status as JSON (useful for status bars).
+        #[clap(long, default_value_t = false)]
+        json: bool,
+    },
+    /// Watch the mpv instance listening on `--socket` until it exits, and mark the cached
+    /// results which it can't play as broken.
+    ///
+    /// Spawned in the background by `--play`, so it can return right after starting mpv.
+    #[clap(hide = true)]
+    Watch,
+}
+
+/// Commands for managing the play queue of the daemon.
+#[derive(Debug, Subcommand)]
+pub enum QueueCommand {
+    /// Add a URL, local path or search query to the end of the queue.
+    Add { query: String },
+    /// Add a URL, local path or search query right after the current media.
+    Next { query: String },
+    /// List the media in the queue.
+    List,
+    /// Remove the media at the given position (as shown by `queue list`).
+    Remove { index: usize },
+    /// Clear the queue and stop the playback.
+    Clear,
+}
+
+/// Commands for inspecting and managing playlists.
+#[derive(Debug, Subcommand)]
+pub enum PlaylistCommand {
+    /// Print the items of a playlist.
+    Show { name: String },
+    /// Rename a playlist.
+    Rename { name: String, new_name: String },
+    /// Copy a playlist to a new name.
+    Copy { name: String, new_name: String },
+    /// Delete a playlist.
+    Delete { name: String },
+    /// Export a playlist for other players.
+    Export {
+        name: String,
+
+        /// The format of the exported playlist.
+        #[clap(long, short = 'f', value_enum, default_value_t = formats::Format::M3u8)]
+        format: formats::Format,
+
+        /// The file to write. Defaults to `<NAME>.<FORMAT>` in the current directory.
+        #[clap(long, short = 'o')]
+        output: Option<String>,
+    },
+    /// Import a M3U/M3U8, PLS or XSPF playlist file.
+    ///
+    /// The items are appended to the playlist if it already exists.
+    Import {
+        file: String,
+
+        /// The name of the playlist. Defaults to the file name without extension.
+        #[clap(long)]
+        name: Option<String>,
+
+        /// The format of the file. Detected from the file extension by default.
+        #[clap(long, short = 'f', value_enum)]
+        format: Option<formats::Format>,
+    },
+    /// Append every video of a YouTube playlist or channel to a playlist.
+    ///
+    /// The URL is remembered, so new videos can be added later with `playlist sync`.
+    ImportUrl { name: String, url: String },
+    /// Append the new videos of the YouTube playlists imported into a playlist.
+    Sync { name: String },
+    /// Download the audio of every item of a playlist for playing it offline.
+    ///
+    /// The items which are already downloaded are skipped.
+    Download { name: String },
+}
+
+/// Commands for inspecting and managing the cached search results.
+///
+/// Queries are looked up the same way `--play` does, so a query prefixed with sources (e.g.,
+/// `sc:query`) or searched with `--source` refers to the entry of those sources.
+#[derive(Debug, Subcommand)]
+pub enum CacheCommand {
+    /// List the cached queries, optionally only the ones containing a text.
+    #[clap(visible_alias = "search")]
+    List { filter: Option<String> },
+    /// Print the cached result of a query.
+    Get { query: String },
+    /// Pin a query to a URL or local file, so it is always played for the query.
+    ///
+    /// Pinned entries never expire and are never searched again.
+    Set { query: String, url: String },
+    /// Remove a query from the cache, so it is searched again.
+    Remove { query: String },
+    /// Remove the expired and broken entries.
+    Prune,
+    /// Remove every entry and reset the statistics.
+    Clear,
+    /// Print the entry counts, the hit rate and the size of the cache.
+    Stats,
+}
+
+/// Commands for inspecting the configuration.
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Print the effective settings and where they come from (command line, environment
+    /// variable, config file or default).
+    Show,
 }
 
 fn main() {
-    let args = Cli::parse();
+    // clap reads the environment variables when building the command
+    unset_negated_env(&Cli::command(), std::env::args());
+    let matches = Cli::command().get_matches();
+    let mut args = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    // The profile and the config file set the defaults of the options which were not given
+    let settings = match config::Config::load(args.config.as_deref())
+        .and_then(|config| apply_config(&mut args, &matches, config))
+    {
+        Ok(settings) => settings,
+        Err(e) => {
+            error(&e);
+            std::process::exit(1);
+        }
+    };
+    // Media is never downloaded offline, even if the config file or the profile downloads it
+    if args.offline {
+        args.download = false;
+    }
+    // `--pick` and `--fuzzy` can also be set by the config file or the profile
+    if matches.value_source("menu") == Some(ValueSource::CommandLine)
+        && args.pick.is_none()
+        && !args.fuzzy
+    {
+        error("--menu is only used with --pick or --fuzzy.");
+        std::process::exit(1);
+    }
+    if let Some(ref path) = args.mpv_path {
+        mpv::set_program(path);
+    }
+    if let Some(ref path) = args.ytdlp_path {
+        youtube::set_program(path);
+    }
+    let socket = args.socket.clone().unwrap_or_else(ipc::socket_path);
+    let daemon_socket = args
+        .daemon_socket
+        .clone()
+        .unwrap_or_else(daemon::socket_path);
 
-    // Determine the URL to be played based on the provided arguments
-    let url: String = if let Some(ref playlist_name) = args.playlist {
-        playlist_name.to_string()
-    } else if let Some(play) = args.play {
-        if utils::is_url(&play) {
-            info("Using provided URL directly.");
-            play
-        } else {
-            if play.is_empty() {
-                error("--play shouldn't be empty.");
-                std::process::exit(1);
+    // Commands run on their own and exit
+    if let Some(ref command) = args.command {
+        let result = match command {
+            Command::Daemon {
+                no_mpris,
+                mpris_bus,
+            } => {
+                let cache_path = args.cache_path.clone();
+                daemon::run(
+                    &daemon_socket,
+                    &socket,
+                    build_mpv_args(&args),
+                    !no_mpris,
+                    mpris_bus.as_deref(),
+                    move |media| mark_broken(media, cache_path.as_deref()),
+                )
             }
-            // Create an cache object
-            let mut cache = cache::Cache::new();
-            if let Err(e) = cache.read() {
-                warning("An error occured while reading cache file.");
-                error(&e);
-                warning("The caching will be not used.");
-                // Get the search result of url to use it without caching.
-                match youtube::search(&play) {
-                    Ok(search_url) => search_url,
-                    Err(e) => {
-                        error("Error fetching URL from YouTube.");
-                        error(&e);
-                        std::process::exit(1);
-                    }
-                }
-            } else if let Some(cached_url) = cache.items.get(&play) {
-                // If the query is already cached, use it.
-                info("Using cached URL.");
-                cached_url.to_string()
-            } else {
-                // If the query is not cached, get search result of query and save it to the cache
-                // And use the result of search
-                match youtube::search(&play) {
-                    Ok(search_url) => {
-                        cache.add(&play, &search_url);
-                        cache.write().unwrap();
-                        search_url
-                    }
-                    Err(e) => {
-                        error("Error fetching URL from YouTube.");
-                        error(&e);
-                        std::process::exit(1);
-                    }
-                }
+            Command::Queue { command } => manage_queue(command, &args, &daemon_socket),
+            Command::Playlists => list_playlists(args.prefix.as_deref()),
+            Command::Playlist { command } => manage_playlist(
+                command,
+                args.prefix.as_deref(),
+                &stream_format(&args),
+                args.offline,
+            ),
+            Command::Cache { command } => manage_cache(command, &args),
+            Command::Watch => ipc::watch_failures(&socket, |media, e| {
+                warning(format!("mpv couldn't play '{}': {}", media, e));
+                mark_broken(media, args.cache_path.as_deref());
+            }),
+            Command::Config {
+                command: ConfigCommand::Show,
+            } => {
+                show_config(&args, &settings);
+                Ok(())
             }
+            _ => control(command, &socket),
+        };
+        if let Err(e) = result {
+            error(&e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Determine the media to be played based on the provided arguments
+    let media: Vec<SearchResult> = if args.playlist.is_some() {
+        Vec::new()
+    } else if let Some(ref play) = args.play {
+        if play.is_empty() {
+            error("--play shouldn't be empty.");
+            std::process::exit(1);
         }
+        resolve_all(play, &args).unwrap_or_else(|e| {
+            error(&e);
+            std::process::exit(1);
+        })
     } else {
         error("Either --playlist or --play must be provided.");
         std::process::exit(1);
     };
 
-    // Prepare MPV arguments based on user preferences
-    let mut mpv_args: mpv::MpvArgs = HashMap::new();
-
-    // Handle the video options
-    if !args.show_video && !args.only_video {
-        mpv_args.insert("--no-video".to_string(), None); // Play without video
-    }
-    if args.only_video {
-        mpv_args.insert("--no-audio".to_string(), None); // Play only video
-    }
-
-    // Set volume
-    mpv_args.insert("--volume".to_string(), Some(args.volume.to_string()));
-
-    // Mute the audio if specified
-    if args.mute {
-        mpv_args.insert("--mute".to_string(), None); // Mute the audio
-    }
-
-    // Loop the audio if specified
-    if args.loop_audio {
-        mpv_args.insert("--loop".to_string(), None); // Loop the audio
-    }
-
     // Handle playlist-related logic if specified
     if let Some(ref playlist_name) = args.playlist {
         if playlist_name.is_empty() {
             error("Playlist name shouldn't be empty.");
             std::process::exit(1);
         }
-        let mut playlist: Playlist = playlist::Playlist::new(playlist_name, args.prefix.as_deref());
+        // Resolve the media to add before opening the playlist, so other playit processes don't
+        // wait for the search or for the user to pick a result
+        let added = args
+            .add
+            .as_ref()
+            .map(|query| Playlist::resolve(query, &args.tags, &searcher(&args)));
 
-        if std::fs::exists(&playlist.path).unwrap_or(false) {
-            // Try reading the playlist if it exists
-            if let Err(e) = playlist.read() {
-                error("Error reading playlist.");
-                error(&e); // Log detailed error message
-                std::process::exit(1);
-            }
-        }
+        // Read the playlist if it exists
+        let mut playlist: Playlist =
+            match playlist::Playlist::open(playlist_name, args.prefix.as_deref()) {
+                Ok(playlist) => playlist,
+                Err(e) => {
+                    error("Error reading playlist.");
+                    error(&e); // Log detailed error message
+                    std::process::exit(1);
+                }
+            };
 
         // Add media to the playlist if the `--add` option is specified
-        if let Some(ref add_query) = args.add {
-            playlist.add(add_query);
+        if let (Some(add_query), Some(items)) = (&args.add, added) {
+            playlist.add(add_query, items);
         }
 
         // Remove media from the playlist if the `--remove` option is specified
         if let Some(ref remove_query) = args.remove {
-            playlist.remove(remove_query);
+            // The playlist is read again if the query has to be searched, so the added media is
+            // written first
+            if args.add.is_some() {
+                playlist = playlist
+                    .write()
+                    .expect("error: Unable to write changes to playlist file.");
+            }
+            if let Err(e) = playlist.remove(remove_query, &searcher(&args)) {
+                error("Error reading playlist.");
+                error(&e);
+                std::process::exit(1);
+            }
         }
 
-        // Shuffle playlist if the `--shuffle` option is specified
-        if args.shuffle {
+        // Shuffle playlist if the `--shuffle` option is specified. The shuffle of a profile only
+        // shuffles the playback, without changing the order of the playlist
+        let reorder =
+            args.shuffle && matches.value_source("shuffle") == Some(ValueSource::CommandLine);
+        if reorder {
             playlist.items.shuffle(&mut rand::rng()); // Shuffle playlist
             info("Playlist items shuffled.");
         }
 
-        if args.add.is_some() || args.remove.is_some() || args.shuffle {
+        if args.add.is_some() || args.remove.is_some() || reorder {
             // Write the updated playlist back to disk
             playlist = playlist
                 .write()
@@ -238,28 +698,1310 @@ fn main() {
                 std::process::exit(1);
             }
 
-            let first_audio = playlist
-                .items
-                .first()
-                .expect("Playlist should not be empty.");
-            for media in &playlist.items[1..] {
-                mpv_args.insert(media.to_string(), None);
+            let mut items = playlist.results(args.offline);
+            if args.shuffle && !reorder {
+                items.shuffle(&mut rand::rng());
+                info("Playing the playlist items shuffled.");
+            }
+            if items.is_empty() {
+                error("None of the playlist items is downloaded, nothing to play offline.");
+                error("Download them with `playit playlist download <NAME>`.");
+                std::process::exit(1);
             }
-            start_instance(first_audio, mpv_args, &args.notification);
+            if items.len() < playlist.items.len() {
+                warning(format!(
+                    "{} items are not downloaded, they will be skipped.",
+                    playlist.items.len() - items.len()
+                ));
+            }
+            // Don't keep the other playit processes waiting while mpv starts playing
+            drop(playlist);
+            play(&items, &args, &socket, &daemon_socket);
         }
     } else {
-        // Play a single media URL (either from --play or search)
-        start_instance(&url, mpv_args, &args.notification);
+        // Play the media given with --play (a URL, local files or a search result)
+        play(&media, &args, &socket, &daemon_socket);
+    }
+}
+
+/// Resolves a URL, local path or search query into the media to play.
+///
+/// The query is classified with `MediaRef`. Local files are played as they are, and directories
+/// with all of their audio files. URLs and search results are replaced with their downloaded
+/// files if there are.
+///
+/// # Parameters
+/// - `query`: The URL, path or search query given by user.
+/// - `args`: The command-line arguments.
+///
+/// # Returns
+/// - `Ok(Vec<SearchResult>)`: The media to play, in order. Never empty.
+/// - `Err(String)`: An error message if the query couldn't be resolved.
+fn resolve_all(query: &str, args: &Cli) -> Result<Vec<SearchResult>, String> {
+    let media = match MediaRef::classify(query) {
+        MediaRef::Local(files) => {
+            if files.is_empty() {
+                return Err(format!("No audio files found in '{}'.", query));
+            }
+            info(format!("Using {} local file(s).", files.len()));
+            return Ok(files
+                .iter()
+                .map(|file| SearchResult::from_path(file))
+                .collect());
+        }
+        MediaRef::Url(url) => {
+            info("Using provided URL directly.");
+            SearchResult::from_url(&url)
+        }
+        MediaRef::YoutubeId(id) => match media::youtube_video(&id, args.offline) {
+            Some(video) => video,
+            None => resolve(&id, &searcher(args), args)?,
+        },
+        MediaRef::Search { query, sources } => {
+            let searcher = searcher(args);
+            let searcher = match sources {
+                Some(sources) => searcher.with_sources(&sources),
+                None => searcher,
+            };
+            resolve(&query, &searcher, args)?
+        }
+    };
+    Ok(vec![use_download(media, args)?])
+}
+
+/// Resolves a search query into a media.
+///
+/// Search queries are looked up in the cache first, and searched on the sources if they are not
+/// cached yet. With `--pick`, the cache is skipped and the user chooses from the search results.
+///
+/// # Parameters
+/// - `query`: The search query given by user.
+/// - `searcher`: The searcher of the sources to search on.
+/// - `args`: The command-line arguments (for `--pick` and `--menu`).
+///
+/// # Returns
+/// - `Ok(SearchResult)`: The media, with its metadata.
+/// - `Err(String)`: An error message if the search failed or nothing was chosen.
+fn resolve(query: &str, searcher: &search::Searcher, args: &Cli) -> Result<SearchResult, String> {
+    let key = searcher.cache_key(query);
+
+    // Open the cache
+    let mut cache = match cache::Cache::open(args.cache_path.as_deref()) {
+        Ok(cache) => cache,
+        Err(e) => {
+            warning("An error occured while opening the cache.");
+            error(&e);
+            warning("The caching will be not used.");
+            // Get the search result of url to use it without caching.
+            return search(query, searcher, args);
+        }
+    };
+    // Expired results are better than nothing without the network
+    cache.set_ttl_days(if args.offline { 0 } else { args.cache_ttl });
+
+    if args.pick.is_none() {
+        if let Some(cached) = cache.get(&key) {
+            // If the query is already cached, use it.
+            info("Using cached URL.");
+            return Ok(cached);
+        }
+        if args.fuzzy {
+            if let Some((similar, result)) = similar(&cache, &key, query, args)? {
+                // Cache the query too, so it is not offered again
+                info("Using cached URL of a similar query.");
+                if let Err(e) = cache.link(&key, &similar) {
+                    warning("Unable to save the cached URL for the query.");
+                    error(&e);
+                }
+                return Ok(result);
+            }
+        }
+    }
+
+    // If the query is not cached, get search result of query and save it to the cache
+    // And use the result of search
+    let result = search(query, searcher, args)?;
+    if let Err(e) = cache.add(&key, &result) {
+        warning("Unable to save the search result to cache.");
+        error(&e);
+    }
+    Ok(result)
+}
+
+/// Offers the cached results of queries similar to a query which is not cached.
+///
+/// # Parameters
+/// - `cache`: The cache to look up.
+/// - `key`: The cache key of the query.
+/// - `query`: The search query given by user.
+/// - `args`: The command-line arguments (for `--menu`).
+///
+/// # Returns
+/// - `Ok(Some((String, SearchResult)))`: The chosen similar query and its cached result.
+/// - `Ok(None)`: If there are no similar queries, or searching the query was chosen.
+/// - `Err(String)`: An error message if nothing valid was chosen.
+fn similar(
+    cache: &cache::Cache,
+    key: &str,
+    query: &str,
+    args: &Cli,
+) -> Result<Option<(String, SearchResult)>, String> {
+    let mut matches = cache.similar(key, 5);
+    if matches.is_empty() {
+        return Ok(None);
+    }
+    let choice = picker::offer(query, &matches, args.menu.as_deref())?;
+    Ok(choice.map(|index| matches.swap_remove(index)))
+}
+
+/// Searches for a query, letting the user choose the result when `--pick` is given.
+///
+/// # Parameters
+/// - `query`: The search query given by user.
+/// - `searcher`: The searcher of the sources to search on.
+/// - `args`: The command-line arguments (for `--pick` and `--menu`).
+///
+/// # Returns
+/// - `Ok(SearchResult)`: The first or chosen result.
+/// - `Err(String)`: An error message if the search failed or nothing was chosen.
+fn search(query: &str, searcher: &search::Searcher, args: &Cli) -> Result<SearchResult, String> {
+    let Some(count) = args.pick else {
+        return searcher.search(query).inspect_err(|_| {
+            error("Error fetching URL from the search sources.");
+        });
+    };
+    if count == 0 {
+        return Err("--pick should be at least 1.".to_string());
+    }
+
+    let mut results = searcher.search_many(query, count).inspect_err(|_| {
+        error("Error fetching search results from the search sources.");
+    })?;
+    let index = picker::pick(&results, args.menu.as_deref())?;
+    Ok(results.swap_remove(index))
+}
+
+/// Replaces the media with its downloaded file, if it was downloaded.
+///
+/// With `--download`, the media is downloaded first. With `--offline`, media which is not
+/// downloaded can't be played.
+///
+/// # Parameters
+/// - `media`: The resolved media.
+/// - `args`: The command-line arguments (for `--download` and `--offline`).
+///
+/// # Returns
+/// - `Ok(SearchResult)`: The media, with the path of the downloaded file as its URL if there is.
+/// - `Err(String)`: An error message if the download failed or the media can't be played
+///   offline.
+fn use_download(media: SearchResult, args: &Cli) -> Result<SearchResult, String> {
+    if !utils::is_url(&media.url) {
+        // Local files don't need to be downloaded
+        return Ok(media);
+    }
+    let path = match download::find(&media.url) {
+        // With `--download`, the media is downloaded again if it is in another format
+        _ if args.download => {
+            download::download(&media.url, &stream_format(args))
+                .inspect_err(|e| {
+                    if youtube::is_unavailable(e) {
+                        mark_broken(&media.url, args.cache_path.as_deref());
+                    }
+                })?
+                .path
+        }
+        Some(download) => {
+            info("Using downloaded file.");
+            download.path
+        }
+        None if args.offline => {
+            return Err(format!(
+                "'{}' is not downloaded, it can't be played offline.",
+                media.name()
+            ))
+        }
+        None => return Ok(media),
+    };
+    Ok(SearchResult { url: path, ..media })
+}
+
+/// Marks the cached results with the given URL as broken, so their queries are searched again.
+fn mark_broken(url: &str, cache_path: Option<&str>) {
+    if let Err(e) = cache::Cache::open(cache_path).and_then(|mut cache| cache.mark_broken(url)) {
+        warning("Unable to mark the cached URL as broken.");
+        error(&e);
     }
 }
 
-fn start_instance(url: &str, mpv_args: mpv::MpvArgs, notification: &str) {
-    let mpv = mpv::Mpv::new(url.to_string(), Some(mpv_args));
+/// Creates the searcher of the sources given by user.
+fn searcher(args: &Cli) -> search::Searcher {
+    search::Searcher::new(&args.source, args.music_dir.as_deref(), args.offline)
+}
+
+/// Prepares MPV arguments based on user preferences.
+fn build_mpv_args(args: &Cli) -> mpv::MpvArgs {
+    let mut mpv_args: mpv::MpvArgs = Vec::new();
+
+    // Handle the video options
+    if !args.show_video && !args.only_video {
+        mpv_args.push(("--no-video".to_string(), None)); // Play without video
+    }
+    if args.only_video {
+        mpv_args.push(("--no-audio".to_string(), None)); // Play only video
+    }
+
+    // Set volume
+    mpv_args.push(("--volume".to_string(), Some(args.volume.to_string())));
+
+    // Mute the audio if specified
+    if args.mute {
+        mpv_args.push(("--mute".to_string(), None)); // Mute the audio
+    }
+
+    // Loop the audio if specified
+    if args.loop_audio {
+        mpv_args.push(("--loop".to_string(), None)); // Loop the audio
+    }
+
+    // Don't let mpv resolve URLs with yt-dlp in offline mode
+    if args.offline {
+        mpv_args.push(("--ytdl".to_string(), Some("no".to_string())));
+    }
+
+    // Choose the streams with yt-dlp, unless it is disabled in offline mode
+    if !args.offline {
+        let video = args.show_video || args.only_video;
+        if let Some(format) = stream_format(args).selector(video) {
+            mpv_args.push(("--ytdl-format".to_string(), Some(format)));
+        }
+        // mpv runs yt-dlp itself for streaming, so it should use the same program
+        if let Some(ref path) = args.ytdlp_path {
+            mpv_args.push((
+                "--script-opts-append".to_string(),
+                Some(format!("ytdl_hook-ytdl_path={}", path)),
+            ));
+        }
+    }
+
+    // Pass the mpv options given by user after the others, so they take precedence
+    mpv_args.extend(args.mpv_args.iter().cloned());
+    mpv_args
+}
+
+/// Returns the quality and the audio format chosen by user.
+fn stream_format(args: &Cli) -> quality::StreamFormat {
+    quality::StreamFormat {
+        quality: args.quality,
+        audio_format: args.audio_format,
+    }
+}
+
+/// Returns the `ytdl-format` for the daemon, which is the one given with `--mpv-opt` if any.
+fn ytdl_format(args: &Cli) -> Option<String> {
+    let given = args
+        .mpv_args
+        .iter()
+        .rev()
+        .find(|(key, _)| key == "--ytdl-format")
+        .map(|(_, value)| value.clone().unwrap_or_default());
+    given.or_else(|| stream_format(args).selector(args.show_video || args.only_video))
+}
+
+/// Plays the given media, sending them to the daemon if it is running.
+///
+/// # Parameters
+/// - `items`: The media to play, in order. Shouldn't be empty.
+/// - `args`: The CLI arguments, used for playback options.
+/// - `socket`: The path of the mpv IPC socket for new instances.
+/// - `daemon_socket`: The path of the daemon socket.
+fn play(items: &[SearchResult], args: &Cli, socket: &str, daemon_socket: &str) {
+    let urls: Vec<String> = items.iter().map(|item| item.url.clone()).collect();
+    if daemon::is_running(daemon_socket) {
+        info("Sending media to playit daemon.");
+        let request = daemon::Request::Play {
+            items: urls,
+            options: daemon::PlaybackOptions {
+                video: args.show_video || args.only_video,
+                audio: !args.only_video,
+                volume: args.volume,
+                mute: args.mute,
+                loop_audio: args.loop_audio,
+                ytdl_format: ytdl_format(args),
+                mpv_options: args.mpv_args.clone(),
+            },
+        };
+        match daemon::send(daemon_socket, &request) {
+            Ok(response) => {
+                let ignored = response.data["ignored_options"].as_array().cloned();
+                for option in ignored.unwrap_or_default() {
+                    warning(format!(
+                        "playit daemon couldn't apply mpv option: {}",
+                        option.as_str().unwrap_or_default()
+                    ));
+                }
+            }
+            Err(e) => {
+                error("playit daemon couldn't play the media.");
+                error(&e);
+                std::process::exit(1);
+            }
+        }
+        if !args.notification.is_empty() {
+            send_notification(&format_notification(&args.notification, &items[0]));
+        }
+        return;
+    }
+
+    let mut mpv_args = build_mpv_args(args);
+    // Listen on the IPC socket so the playback can be controlled later
+    mpv_args.push(("--input-ipc-server".to_string(), Some(socket.to_string())));
+    start_instance(urls, &items[0], mpv_args, socket, args);
+}
+
+/// Spawns a new mpv instance playing the given media.
+///
+/// A `playit watch` process is spawned in the background too, which marks the media mpv can't
+/// play (e.g., deleted videos) as broken in the cache, so their queries are searched again next
+/// time.
+///
+/// # Parameters
+/// - `urls`: The URLs or paths of the media, in order.
+/// - `first`: The first media, for the notification.
+/// - `mpv_args`: The arguments of mpv, listening on `socket`.
+/// - `socket`: The path of the mpv IPC socket.
+/// - `args`: The CLI arguments (for `--notification` and `--cache-path`).
+fn start_instance(
+    urls: Vec<String>,
+    first: &SearchResult,
+    mpv_args: mpv::MpvArgs,
+    socket: &str,
+    args: &Cli,
+) {
+    let mpv = mpv::Mpv::new(urls, Some(mpv_args));
+    // mpv replaces the socket of an older instance, but remove it first so that instance is
+    // never watched instead of the new one
+    let _ = std::fs::remove_file(socket);
     info("Spawning mpv instance.");
     let id = mpv.spawn();
-    if !notification.is_empty() {
-        send_notification(&notification.replace("{}", url));
+    if !args.notification.is_empty() {
+        send_notification(&format_notification(&args.notification, first));
     }
     info("Process id:");
     println!("  {}", id);
+    if id == 0 {
+        return;
+    }
+
+    if let Err(e) = spawn_watcher(socket, args.cache_path.as_deref()) {
+        warning("Unable to watch the playback for media which can't be played.");
+        error(&e);
+    }
+}
+
+/// Spawns a `playit watch` process in the background, which watches the mpv instance listening
+/// on the given socket.
+///
+/// # Parameters
+/// - `socket`: The path of the mpv IPC socket.
+/// - `cache_path`: The custom path of the cache database, if any.
+///
+/// # Returns
+/// - `Ok(())` if the process was spawned.
+/// - `Err(String)` if the process couldn't be spawned.
+fn spawn_watcher(socket: &str, cache_path: Option<&str>) -> Result<(), String> {
+    let program =
+        std::env::current_exe().map_err(|e| format!("Unable to find the playit program: {}", e))?;
+    let mut command = std::process::Command::new(program);
+    command.args(["--socket", socket]);
+    if let Some(path) = cache_path {
+        command.args(["--cache-path", path]);
+    }
+    // Nothing is printed, the terminal may be gone by the time mpv fails
+    command
+        .arg("watch")
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .map(|_| ())
+        .map_err(|e| format!("Unable to spawn the watcher: {}", e))
+}
+
+/// Sends a control command to the mpv instance listening on the given socket.
+///
+/// # Parameters
+/// - `command`: The control command given by user.
+/// - `socket`: The path of the mpv IPC socket.
+///
+/// # Returns
+/// - `Ok(())` if mpv accepted the command.
+/// - `Err(String)` if mpv is not running or rejected the command.
+fn control(command: &Command, socket: &str) -> Result<(), String> {
+    let mut mpv = ipc::MpvIpc::connect(socket)?;
+    match command {
+        Command::Pause => {
+            mpv.set_property("pause", json!(true))?;
+            info("Playback paused.");
+        }
+        Command::Resume => {
+            mpv.set_property("pause", json!(false))?;
+            info("Playback resumed.");
+        }
+        Command::Toggle => {
+            mpv.command(json!(["cycle", "pause"]))?;
+            info("Playback toggled.");
+        }
+        Command::Next => {
+            mpv.command(json!(["playlist-next"]))?;
+            info("Skipped to the next item.");
+        }
+        Command::Prev => {
+            mpv.command(json!(["playlist-prev"]))?;
+            info("Went back to the previous item.");
+        }
+        Command::Seek { seconds, absolute } => {
+            let mode = if *absolute { "absolute" } else { "relative" };
+            mpv.command(json!(["seek", seconds, mode]))?;
+            info(format!("Seeked {} seconds ({}).", seconds, mode));
+        }
+        Command::Stop => {
+            mpv.command(json!(["quit"]))?;
+            info("Playback stopped.");
+        }
+        Command::Status { json } => {
+            let status = mpv.status()?;
+            if *json {
+                let content = serde_json::to_string(&status)
+                    .map_err(|_| "Failed to convert status to JSON.".to_string())?;
+                println!("{}", content);
+            } else {
+                print_status(&status);
+            }
+        }
+        Command::Daemon { .. }
+        | Command::Queue { .. }
+        | Command::Playlists
+        | Command::Playlist { .. }
+        | Command::Cache { .. }
+        | Command::Config { .. }
+        | Command::Watch => {
+            unreachable!("Only the control commands are handled here.")
+        }
+    }
+    Ok(())
+}
+
+/// Prints the playback status in a human readable form.
+fn print_status(status: &ipc::Status) {
+    let unknown = String::from("unknown");
+    println!("Title: {}", status.title.as_ref().unwrap_or(&unknown));
+    println!("URL: {}", status.url.as_ref().unwrap_or(&unknown));
+    println!(
+        "Position: {} / {}",
+        status
+            .position
+            .map(utils::format_duration)
+            .unwrap_or(unknown.clone()),
+        status
+            .duration
+            .map(utils::format_duration)
+            .unwrap_or(unknown.clone())
+    );
+    if let Some(volume) = status.volume {
+        println!("Volume: {}", volume);
+    }
+    println!(
+        "State: {}",
+        if status.paused { "paused" } else { "playing" }
+    );
+    if let (Some(index), Some(count)) = (status.playlist_index, status.playlist_count) {
+        println!("Playlist: {}/{}", index + 1, count);
+    }
+}
+
+/// Sends a queue command to the daemon.
+///
+/// # Parameters
+/// - `command`: The queue command given by user.
+/// - `args`: The command-line arguments (for resolving the queries).
+/// - `daemon_socket`: The path of the daemon socket.
+///
+/// # Returns
+/// - `Ok(())` if the daemon handled the command.
+/// - `Err(String)` if the daemon is not running or the command failed.
+fn manage_queue(command: &QueueCommand, args: &Cli, daemon_socket: &str) -> Result<(), String> {
+    if !daemon::is_running(daemon_socket) {
+        return Err("playit daemon is not running. Start it with `playit daemon`.".to_string());
+    }
+    let request = match command {
+        QueueCommand::Add { query } | QueueCommand::Next { query } => {
+            if query.is_empty() {
+                return Err("The query shouldn't be empty.".to_string());
+            }
+            let next = matches!(command, QueueCommand::Next { .. });
+            let mut items = resolve_all(query, args)?;
+            if next {
+                // Every item is added right after the current one, so they are added reversed
+                items.reverse();
+            }
+            let mut response = daemon::Response::default();
+            for item in items {
+                response = daemon::send(
+                    daemon_socket,
+                    &daemon::Request::Enqueue {
+                        item: item.url,
+                        next,
+                    },
+                )?;
+            }
+            info(response.message);
+            return Ok(());
+        }
+        QueueCommand::List => daemon::Request::List,
+        QueueCommand::Remove { index } => daemon::Request::Remove {
+            index: index
+                .checked_sub(1)
+                .ok_or("The positions start from 1.".to_string())?,
+        },
+        QueueCommand::Clear => daemon::Request::Clear,
+    };
+
+    let response = daemon::send(daemon_socket, &request)?;
+    if let QueueCommand::List = command {
+        let current = response.data["current"].as_u64();
+        let items = response.data["items"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default();
+        if items.is_empty() {
+            info("The queue is empty.");
+        }
+        for (index, item) in items.iter().enumerate() {
+            let marker = if current == Some(index as u64) {
+                "*"
+            } else {
+                " "
+            };
+            println!(
+                "{} {}. {}",
+                marker,
+                index + 1,
+                item.as_str().unwrap_or_default()
+            );
+        }
+    } else {
+        info(response.message);
+    }
+    Ok(())
+}
+
+/// Prints all playlists with their item counts and total durations.
+///
+/// # Parameters
+/// - `prefix`: The custom playlist directory, if any.
+///
+/// # Returns
+/// - `Ok(())` if the playlists were listed.
+/// - `Err(String)` if the playlist directory couldn't be read.
+fn list_playlists(prefix: Option<&str>) -> Result<(), String> {
+    let names = playlist::names(prefix)?;
+    if names.is_empty() {
+        info("There are no playlists. Create one with `--playlist <NAME> --add <QUERY>`.");
+    }
+    for name in names {
+        match Playlist::load(&name, prefix) {
+            Ok(playlist) => println!(
+                "{} ({} items, {})",
+                name,
+                playlist.items.len(),
+                utils::format_duration(playlist.total_duration())
+            ),
+            Err(e) => println!("{} (unreadable: {})", name, e),
+        }
+    }
+    Ok(())
+}
+
+/// Runs a playlist command.
+///
+/// # Parameters
+/// - `command`: The playlist command given by user.
+/// - `prefix`: The custom playlist directory, if any.
+/// - `format`: The quality and the format of the downloaded audio.
+/// - `offline`: Whether the network must not be used.
+///
+/// # Returns
+/// - `Ok(())` if the command succeeded.
+/// - `Err(String)` if the playlist doesn't exist or the command failed, or it needs the network
+///   in offline mode.
+fn manage_playlist(
+    command: &PlaylistCommand,
+    prefix: Option<&str>,
+    format: &quality::StreamFormat,
+    offline: bool,
+) -> Result<(), String> {
+    if offline {
+        let network = match command {
+            PlaylistCommand::ImportUrl { .. } => Some("import-url"),
+            PlaylistCommand::Sync { .. } => Some("sync"),
+            PlaylistCommand::Download { .. } => Some("download"),
+            _ => None,
+        };
+        if let Some(network) = network {
+            return Err(format!(
+                "`playlist {}` needs the network, it can't be used with `--offline`.",
+                network
+            ));
+        }
+    }
+    match command {
+        PlaylistCommand::Show { name } => {
+            let playlist = Playlist::load(name, prefix)?;
+            println!(
+                "{} ({} items, {})",
+                name,
+                playlist.items.len(),
+                utils::format_duration(playlist.total_duration())
+            );
+            for (index, item) in playlist.items.iter().enumerate() {
+                let mut line = format!("{}. {}", index + 1, item.name());
+                if let Some(ref channel) = item.channel {
+                    line.push_str(&format!(" - {}", channel));
+                }
+                if let Some(duration) = item.duration {
+                    line.push_str(&format!(" [{}]", utils::format_duration(duration)));
+                }
+                if !item.tags.is_empty() {
+                    line.push_str(&format!(" #{}", item.tags.join(" #")));
+                }
+                println!("{}", line);
+                println!("   {}", item.url);
+                if let Some(download) = download::find(&item.url) {
+                    println!("   {} ({})", download.path, download.format.label());
+                }
+            }
+        }
+        PlaylistCommand::Rename { name, new_name } => {
+            Playlist::load(name, prefix)?.rename(new_name, prefix)?;
+        }
+        PlaylistCommand::Copy { name, new_name } => {
+            Playlist::load(name, prefix)?.copy(new_name, prefix)?;
+        }
+        PlaylistCommand::Delete { name } => {
+            Playlist::load(name, prefix)?.delete()?;
+        }
+        PlaylistCommand::Export {
+            name,
+            format,
+            output,
+        } => {
+            let playlist = Playlist::load(name, prefix)?;
+            let output = output
+                .clone()
+                .unwrap_or_else(|| format!("{}.{}", name, format.extension()));
+            let content = formats::export(name, &playlist.items, *format);
+            std::fs::write(&output, content)
+                .map_err(|e| format!("Unable to write {}: {}", output, e))?;
+            info(format!("Playlist exported to: {}", output));
+        }
+        PlaylistCommand::Import { file, name, format } => {
+            let format = format
+                .or_else(|| formats::Format::detect(file))
+                .ok_or("Unable to detect the playlist format. Use `--format`.".to_string())?;
+            let path = std::path::Path::new(file);
+            let name = match name {
+                Some(name) => name.to_string(),
+                None => path
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().to_string())
+                    .ok_or("Unable to get playlist name from file. Use `--name`.".to_string())?,
+            };
+            let content = std::fs::read_to_string(file)
+                .map_err(|e| format!("Unable to read {}: {}", file, e))?;
+            // `parent` is empty for files in the current directory, so the path is made absolute
+            let absolute = std::fs::canonicalize(path)
+                .map_err(|e| format!("Unable to read {}: {}", file, e))?;
+            let base = absolute.parent().unwrap_or(std::path::Path::new("/"));
+            let items = formats::import(&content, format, base)?;
+
+            let mut playlist = Playlist::open(&name, prefix)?;
+            info(format!(
+                "Importing {} items to playlist: {}",
+                items.len(),
+                name
+            ));
+            playlist.items.extend(items);
+            playlist.write()?;
+        }
+        PlaylistCommand::ImportUrl { name, url } => {
+            if !utils::is_url(url) {
+                return Err("A URL of a YouTube playlist or channel should be given.".to_string());
+            }
+            let mut playlist = Playlist::open(name, prefix)?;
+            playlist.import_url(url)?;
+            playlist.write()?;
+        }
+        PlaylistCommand::Sync { name } => {
+            let mut playlist = Playlist::load(name, prefix)?;
+            let added = playlist.sync()?;
+            if added > 0 {
+                playlist.write()?;
+            }
+            info(format!("Playlist synced, {} new items added.", added));
+        }
+        PlaylistCommand::Download { name } => {
+            let mut playlist = Playlist::load(name, prefix)?;
+            let downloaded = playlist.download(format)?;
+            let missing = playlist
+                .items
+                .iter()
+                .filter(|item| item.local_file().is_none())
+                .count();
+            // The paths and the formats of the files downloaded earlier are recorded too
+            playlist.write()?;
+            if missing > 0 {
+                warning(format!("{} items couldn't be downloaded.", missing));
+            }
+            info(format!("Playlist downloaded, {} new files.", downloaded));
+        }
+    }
+    Ok(())
+}
+
+/// Runs a cache command.
+///
+/// # Parameters
+/// - `command`: The cache command given by user.
+/// - `args`: The command-line arguments (for the sources and `--cache-ttl`).
+///
+/// # Returns
+/// - `Ok(())` if the command succeeded.
+/// - `Err(String)` if the cache couldn't be read or written, or the query is not cached.
+fn manage_cache(command: &CacheCommand, args: &Cli) -> Result<(), String> {
+    let mut cache = cache::Cache::open(args.cache_path.as_deref())?;
+    cache.set_ttl_days(args.cache_ttl);
+
+    match command {
+        CacheCommand::List { filter } => {
+            let filter = filter.as_deref().map(str::to_lowercase);
+            let entries: Vec<(String, cache::CacheEntry)> = cache
+                .entries()?
+                .into_iter()
+                .filter(|(query, entry)| match filter {
+                    Some(ref filter) => {
+                        query.to_lowercase().contains(filter)
+                            || entry.result.name().to_lowercase().contains(filter)
+                    }
+                    None => true,
+                })
+                .collect();
+            if entries.is_empty() {
+                info("There are no cached queries.");
+            }
+            for (query, entry) in entries {
+                println!(
+                    "{} -> {}{}",
+                    query,
+                    entry.result.name(),
+                    flags(&entry, cache.ttl)
+                );
+                println!(
+                    "   {} (resolved {}, used {})",
+                    entry.result.url,
+                    utils::format_age(entry.resolved_at),
+                    utils::format_age(entry.last_used)
+                );
+            }
+        }
+        CacheCommand::Get { query } => {
+            let key = cache_key(&cache, query, args);
+            let entry = cache
+                .entry(&key)
+                .ok_or(format!("'{}' is not cached.", query))?;
+            println!("Query: {}{}", key, flags(&entry, cache.ttl));
+            println!("Title: {}", entry.result.name());
+            if let Some(ref uploader) = entry.result.uploader {
+                println!("Uploader: {}", uploader);
+            }
+            if let Some(duration) = entry.result.duration {
+                println!("Duration: {}", utils::format_duration(duration));
+            }
+            println!("URL: {}", entry.result.url);
+            println!("Resolved: {}", utils::format_age(entry.resolved_at));
+            println!("Last used: {}", utils::format_age(entry.last_used));
+        }
+        CacheCommand::Set { query, url } => {
+            // A URL is expected, so a text which looks like a video ID is taken for one
+            let media = match MediaRef::classify(url) {
+                MediaRef::YoutubeId(id) => MediaRef::Url(media::youtube_url(&id)),
+                media => media,
+            };
+            let result = match media {
+                MediaRef::Url(url) if args.offline => SearchResult::from_url(&url),
+                MediaRef::Url(url) => youtube::metadata(&url).unwrap_or_else(|e| {
+                    warning("Unable to fetch the metadata of the URL, only the URL is cached.");
+                    error(&e);
+                    SearchResult::from_url(&url)
+                }),
+                MediaRef::Local(files) if files.len() == 1 => SearchResult::from_path(&files[0]),
+                _ => return Err(format!("'{}' is not a URL or a local file.", url)),
+            };
+            let key = cache_key(&cache, query, args);
+            cache.pin(&key, &result)?;
+        }
+        CacheCommand::Remove { query } => {
+            let key = cache_key(&cache, query, args);
+            if !cache.remove(&key)? {
+                return Err(format!("'{}' is not cached.", query));
+            }
+            info(format!("Removed query from cache: {}", key));
+        }
+        CacheCommand::Prune => {
+            let removed = cache.prune()?;
+            info(format!("Cache pruned, {} entries removed.", removed));
+        }
+        CacheCommand::Clear => {
+            let count = cache.clear()?;
+            info(format!("Cache cleared, {} entries removed.", count));
+        }
+        CacheCommand::Stats => {
+            let stats = cache.stats()?;
+            let lookups = stats.hits + stats.misses;
+            // The write-ahead log of the database is a part of the cache too
+            let size: u64 = [cache.path.clone(), format!("{}-wal", cache.path)]
+                .iter()
+                .filter_map(|path| std::fs::metadata(path).ok())
+                .map(|metadata| metadata.len())
+                .sum();
+            println!("File: {} ({})", cache.path, utils::format_size(size));
+            println!("Entries: {}", stats.entries);
+            println!("Pinned: {}", stats.pinned);
+            println!("Broken: {}", stats.broken);
+            println!("Expired: {}", stats.expired);
+            println!("Hits: {}", stats.hits);
+            println!("Misses: {}", stats.misses);
+            if lookups > 0 {
+                println!(
+                    "Hit rate: {:.1}%",
+                    stats.hits as f64 * 100.0 / lookups as f64
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the key of a query in the cache, as `--play` would look it up.
+///
+/// Keys printed by `cache list` are accepted as they are too.
+fn cache_key(cache: &cache::Cache, query: &str, args: &Cli) -> String {
+    if cache.entry(query).is_some() {
+        return query.to_string();
+    }
+    match MediaRef::classify(query) {
+        MediaRef::Search {
+            query,
+            sources: Some(sources),
+        } => searcher(args).with_sources(&sources).cache_key(&query),
+        _ => searcher(args).cache_key(query.trim()),
+    }
+}
+
+/// Returns the flags of a cache entry, such as ` [pinned]`.
+fn flags(entry: &cache::CacheEntry, ttl: u64) -> String {
+    let mut flags = String::new();
+    if entry.pinned {
+        flags.push_str(" [pinned]");
+    }
+    if entry.broken {
+        flags.push_str(" [broken]");
+    }
+    if entry.is_expired(ttl) {
+        flags.push_str(" [expired]");
+    }
+    flags
+}
+
+/// Removes the environment variables of the flags turned off with their `--no-` options.
+///
+/// clap only lets `overrides_with` override options given on the command line, and reports the
+/// `--no-` options as conflicting with the flags set by their environment variables.
+///
+/// # Parameters
+/// - `command`: The command-line interface.
+/// - `args`: The command-line arguments.
+fn unset_negated_env(command: &clap::Command, args: impl Iterator<Item = String>) {
+    for arg in args {
+        let Some(long) = arg
+            .strip_prefix("--")
+            .filter(|long| long.starts_with("no-"))
+        else {
+            continue;
+        };
+        let flag = command
+            .get_arguments()
+            .find(|a| a.get_long() == Some(long))
+            .and_then(|negation| negation.get_id().as_str().strip_prefix("no_"))
+            .and_then(|id| command.get_arguments().find(|a| a.get_id() == id));
+        if let Some(env) = flag.and_then(|flag| flag.get_env()) {
+            std::env::remove_var(env);
+        }
+    }
+}
+
+/// Applies the settings of the profile and of the config file to the options, which have the
+/// same field names in `Cli`, `Profile` and `Config`.
+///
+/// The `flags` and the `profile` options are read from both of them, and the `config` options
+/// only from the config file. The flags can also be turned off with their `--no-` options. The
+/// names are the names of the settings in the config file.
+macro_rules! apply_settings {
+    (
+        $layers:ident, $args:ident, $profile:ident, $config:ident;
+        flags: { $($flag:ident: $flag_name:literal),* $(,)? },
+        profile: { $($field:ident: $name:literal),* $(,)? },
+        config: { $($config_field:ident: $config_name:literal),* $(,)? }
+    ) => {
+        $(
+            $layers.apply_flag(
+                stringify!($flag),
+                $flag_name,
+                &mut $args.$flag,
+                $profile.$flag,
+                $config.$flag,
+            );
+        )*
+        $(
+            $layers.apply(
+                stringify!($field),
+                $name,
+                &mut $args.$field,
+                $profile.$field,
+                $config.$field,
+            );
+        )*
+        $(
+            $layers.apply(
+                stringify!($config_field),
+                $config_name,
+                &mut $args.$config_field,
+                None,
+                $config.$config_field,
+            );
+        )*
+    };
+}
+
+/// Applies the settings of the selected profile and of the config file to the options which
+/// were not given on the command line or with environment variables.
+///
+/// # Parameters
+/// - `args`: The command-line arguments, updated with the settings.
+/// - `matches`: The parsed arguments, telling where the values of the options come from.
+/// - `config`: The settings of the config file.
+///
+/// # Returns
+/// - `Ok(Vec<Setting>)`: The effective settings with where they come from, for `config show`.
+/// - `Err(String)`: An error message if the selected profile doesn't exist.
+fn apply_config(
+    args: &mut Cli,
+    matches: &ArgMatches,
+    config: config::Config,
+) -> Result<Vec<config::Setting>, String> {
+    let selected = config.profile(args.profile.as_deref(), args.playlist.as_deref())?;
+    let (name, profile) = selected.unzip();
+    let profile = profile.unwrap_or_default();
+    let mut layers = Layers {
+        matches,
+        profile: name.as_deref(),
+        settings: Vec::new(),
+    };
+    let origin = match matches.value_source("profile") {
+        Some(ValueSource::CommandLine) => config::Origin::Cli,
+        Some(ValueSource::EnvVariable) => config::Origin::Env,
+        _ if name.is_some() => config::Origin::Config,
+        _ => config::Origin::Default,
+    };
+    layers
+        .settings
+        .push(config::Setting::new("profile", &name, origin));
+
+    apply_settings!(layers, args, profile, config;
+        // The options which can be set by profiles and by the config file
+        flags: {
+            show_video: "show-video",
+            only_video: "only-video",
+            loop_audio: "loop",
+            mute: "mute",
+            shuffle: "shuffle",
+            download: "download",
+            offline: "offline",
+            fuzzy: "fuzzy",
+        },
+        profile: {
+            volume: "volume",
+            notification: "notification",
+            pick: "pick",
+            menu: "menu",
+            source: "source",
+            quality: "quality",
+            audio_format: "audio-format",
+        },
+        // The options which can only be set by the config file
+        config: {
+            prefix: "prefix",
+            socket: "socket",
+            daemon_socket: "daemon-socket",
+            music_dir: "music-dir",
+            cache_ttl: "cache-ttl",
+            mpv_path: "mpv-path",
+            ytdlp_path: "ytdlp-path",
+            cache_path: "cache-path",
+        }
+    );
+
+    // The mpv options of every layer are passed, the later ones taking precedence in mpv
+    let mut mpv_options = Vec::new();
+    let profile_origin = config::Origin::Profile(name.clone().unwrap_or_default());
+    let mut profile_options = config::mpv_options(&profile.mpv)?;
+    profile_options.extend(profile.mpv_options.iter().cloned());
+    for (options, origin) in [
+        (config::mpv_options(&config.mpv)?, config::Origin::Config),
+        (profile_options, profile_origin),
+        (args.mpv_options.clone(), config::Origin::Cli),
+    ] {
+        if !options.is_empty() {
+            layers
+                .settings
+                .push(config::Setting::new("mpv", &options, origin));
+        }
+        mpv_options.extend(options);
+    }
+    args.mpv_args = mpv_options
+        .iter()
+        .map(|option| mpv::parse_option(option))
+        .collect::<Result<_, _>>()?;
+    Ok(layers.settings)
+}
+
+/// The settings applied to the options, below the command line and the environment variables.
+///
+/// # Fields
+/// - `matches`: The parsed arguments, telling where the values of the options come from.
+/// - `profile`: The name of the selected profile, if any.
+/// - `settings`: The effective settings applied so far.
+struct Layers<'a> {
+    matches: &'a ArgMatches,
+    profile: Option<&'a str>,
+    settings: Vec<config::Setting>,
+}
+
+impl Layers<'_> {
+    /// Sets a flag like `apply`, unless it was turned off with its `--no-` option on the command
+    /// line.
+    fn apply_flag(
+        &mut self,
+        id: &str,
+        name: &'static str,
+        option: &mut bool,
+        profile: Option<bool>,
+        config: Option<bool>,
+    ) {
+        let negated = format!("no_{}", id);
+        if self.matches.value_source(&negated) == Some(ValueSource::CommandLine) {
+            *option = false;
+            self.settings
+                .push(config::Setting::new(name, option, config::Origin::Cli));
+            return;
+        }
+        self.apply(id, name, option, profile, config);
+    }
+
+    /// Sets an option to its value in the profile or in the config file, unless it was given on
+    /// the command line or with its environment variable, and records where its value comes from.
+    ///
+    /// # Parameters
+    /// - `id`: The ID of the option (its field name in `Cli`).
+    /// - `name`: The name of the setting in the config file.
+    /// - `option`: The value of the option.
+    /// - `profile`: The value in the selected profile, if it is set there. Values of optional
+    ///   options are given without `Some`.
+    /// - `config`: The value in the config file, if it is set there.
+    fn apply<T: serde::Serialize, V: Into<T>>(
+        &mut self,
+        id: &str,
+        name: &'static str,
+        option: &mut T,
+        profile: Option<V>,
+        config: Option<V>,
+    ) {
+        let origin = match (self.matches.value_source(id), profile, config) {
+            (Some(ValueSource::CommandLine), _, _) => config::Origin::Cli,
+            (Some(ValueSource::EnvVariable), _, _) => config::Origin::Env,
+            (_, Some(value), _) => {
+                *option = value.into();
+                config::Origin::Profile(self.profile.unwrap_or_default().to_string())
+            }
+            (_, None, Some(value)) => {
+                *option = value.into();
+                config::Origin::Config
+            }
+            (_, None, None) => config::Origin::Default,
+        };
+        self.settings
+            .push(config::Setting::new(name, option, origin));
+    }
+}
+
+/// Prints the path of the config file and the effective settings.
+fn show_config(args: &Cli, settings: &[config::Setting]) {
+    match args.config.clone().or_else(config::default_path) {
+        Some(path) if std::fs::exists(&path).unwrap_or(false) => {
+            println!("# Config file: {}", path)
+        }
+        Some(path) => println!("# Config file: {} (not found)", path),
+        None => println!("# Config file: unknown"),
+    }
+    for setting in settings {
+        println!("{}", setting);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a value for a setting in TOML syntax, for setting every option in the tests.
+    fn sample(name: &str) -> &'static str {
+        match name {
+            "show-video" | "only-video" | "loop" | "mute" | "shuffle" | "download" | "offline"
+            | "fuzzy" => "true",
+            "volume" => "40",
+            "pick" => "3",
+            "cache-ttl" => "5",
+            "source" => "[\"ytm\", \"yt\"]",
+            "quality" => "\"low\"",
+            "audio-format" => "\"opus\"",
+            "notification" | "menu" | "prefix" | "socket" | "daemon-socket" | "music-dir"
+            | "mpv-path" | "ytdlp-path" | "cache-path" => "\"value\"",
+            _ => panic!(
+                "No sample value for setting '{}', add one to the tests.",
+                name
+            ),
+        }
+    }
+
+    /// Returns the names of the settings of a config file section, except the nested ones.
+    fn names<T: serde::Serialize>(section: T) -> Vec<String> {
+        let nested = ["profile", "profiles", "playlists", "mpv", "mpv-options"];
+        match serde_json::to_value(section).unwrap() {
+            serde_json::Value::Object(fields) => fields
+                .keys()
+                .filter(|name| !nested.contains(&name.as_str()))
+                .cloned()
+                .collect(),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Applies a config file to the given arguments, returning the effective settings.
+    fn settings(args: &[&str], content: &str) -> Vec<config::Setting> {
+        let matches = Cli::command().try_get_matches_from(args).unwrap();
+        let mut cli = Cli::from_arg_matches(&matches).unwrap();
+        let config: config::Config = toml::from_str(content).unwrap();
+        apply_config(&mut cli, &matches, config).unwrap()
+    }
+
+    fn origin<'a>(settings: &'a [config::Setting], name: &str) -> Option<&'a config::Origin> {
+        settings
+            .iter()
+            .find(|setting| setting.name == name)
+            .map(|setting| &setting.origin)
+    }
+
+    #[test]
+    fn every_config_setting_is_applied() {
+        let names = names(config::Config::default());
+        let content: String = names
+            .iter()
+            .map(|name| format!("{} = {}\n", name, sample(name)))
+            .collect();
+        let settings = settings(&["playit", "config", "show"], &content);
+        for name in &names {
+            assert_eq!(
+                origin(&settings, name),
+                Some(&config::Origin::Config),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn every_profile_setting_is_applied() {
+        let names = names(config::Profile::default());
+        let content: String = names
+            .iter()
+            .map(|name| format!("{} = {}\n", name, sample(name)))
+            .collect();
+        let content = format!("[profiles.test]\n{}", content);
+        let settings = settings(&["playit", "--profile", "test", "config", "show"], &content);
+        for name in &names {
+            assert_eq!(
+                origin(&settings, name),
+                Some(&config::Origin::Profile(String::from("test"))),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn command_line_takes_precedence() {
+        let content = "volume = 20\n[profiles.test]\nvolume = 30\nloop = true\n";
+        let args = [
+            "playit",
+            "--profile",
+            "test",
+            "--volume",
+            "10",
+            "config",
+            "show",
+        ];
+        let settings = settings(&args, content);
+        assert_eq!(origin(&settings, "volume"), Some(&config::Origin::Cli));
+        assert_eq!(
+            origin(&settings, "loop"),
+            Some(&config::Origin::Profile(String::from("test")))
+        );
+        assert_eq!(origin(&settings, "mute"), Some(&config::Origin::Default));
+    }
+
+    #[test]
+    fn flags_can_be_turned_off() {
+        let content = "mute = true\n[profiles.test]\nloop = true\nshuffle = true\n";
+        let args = [
+            "playit",
+            "--profile",
+            "test",
+            "--no-mute",
+            "--no-loop",
+            "--shuffle",
+            "--no-shuffle",
+            "config",
+            "show",
+        ];
+        let matches = Cli::command().try_get_matches_from(args).unwrap();
+        let mut cli = Cli::from_arg_matches(&matches).unwrap();
+        let config: config::Config = toml::from_str(content).unwrap();
+        let settings = apply_config(&mut cli, &matches, config).unwrap();
+        assert!(!cli.mute && !cli.loop_audio && !cli.shuffle);
+        assert_eq!(origin(&settings, "mute"), Some(&config::Origin::Cli));
+        assert_eq!(origin(&settings, "loop"), Some(&config::Origin::Cli));
+
+        // The last of a flag and its negation wins
+        let args = ["playit", "--no-mute", "--mute", "config", "show"];
+        let matches = Cli::command().try_get_matches_from(args).unwrap();
+        assert!(Cli::from_arg_matches(&matches).unwrap().mute);
+    }
 }
@@ -0,0 +1,250 @@
+use crate::download;
+use crate::log::info;
+use crate::search::Source;
+use crate::utils::{is_url, local_files};
+use crate::youtube::{self, SearchResult};
+use clap::ValueEnum;
+
+/// Length of YouTube video IDs.
+const YOUTUBE_ID_LENGTH: usize = 11;
+
+/// What a media argument given by user (e.g., to `--play` or `--add`) refers to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MediaRef {
+    /// A URL with any scheme (e.g., `https://`, `ytdl://`, `rtmp://`), played as it is.
+    Url(String),
+    /// Local files: a single file, or the audio files of a directory. Empty for directories
+    /// without audio files.
+    Local(Vec<String>),
+    /// A text which looks like a YouTube video ID (e.g., `dQw4w9WgXcQ`). Names and words can look
+    /// like IDs too (e.g., `Radiohead12`), so it is searched if `youtube_video` can't find it.
+    YoutubeId(String),
+    /// A search query, with the sources given with a `provider:` prefix (e.g., `sc:query`).
+    Search {
+        query: String,
+        sources: Option<Vec<Source>>,
+    },
+}
+
+impl MediaRef {
+    /// Classifies a media argument given by user.
+    ///
+    /// The argument is checked in this order:
+    /// - URLs with a scheme (except `file://`) are used as they are.
+    /// - `file://` URIs and existing paths are local files. `file://` URIs of missing files are
+    ///   used as they are.
+    /// - Queries prefixed with source names (e.g., `sc:`, `ytm,yt:`) are searched on them.
+    /// - URLs without a scheme (e.g., `youtu.be/xyz`, `www.youtube.com/watch?v=xyz`) get `https://`.
+    /// - Texts which look like YouTube video IDs (e.g., `dQw4w9WgXcQ`) are possible IDs.
+    /// - Anything else is searched on the sources given with `--source`.
+    ///
+    /// # Parameters
+    /// - `text`: The argument given by user.
+    ///
+    /// # Returns
+    /// The classified media reference.
+    pub fn classify(text: &str) -> MediaRef {
+        let text = text.trim();
+        if is_url(text) && !text.starts_with("file://") {
+            return MediaRef::Url(text.to_string());
+        }
+        if let Some(files) = local_files(text) {
+            return MediaRef::Local(files);
+        }
+        // mpv reports the files which don't exist, they are never searched
+        if is_url(text) {
+            return MediaRef::Url(text.to_string());
+        }
+        if let Some((sources, query)) = source_prefix(text) {
+            return MediaRef::Search {
+                query: query.to_string(),
+                sources: Some(sources),
+            };
+        }
+        if is_schemeless_url(text) {
+            return MediaRef::Url(format!("https://{}", text));
+        }
+        if is_youtube_id(text) {
+            return MediaRef::YoutubeId(text.to_string());
+        }
+        MediaRef::Search {
+            query: text.to_string(),
+            sources: None,
+        }
+    }
+}
+
+/// Splits the `provider:` prefix of a search query, if it names known sources.
+fn source_prefix(text: &str) -> Option<(Vec<Source>, &str)> {
+    let (prefix, query) = text.split_once(':')?;
+    let query = query.trim();
+    if query.is_empty() || prefix.contains(char::is_whitespace) {
+        return None;
+    }
+    let sources = prefix
+        .split(',')
+        .map(|name| Source::from_str(name, true).ok())
+        .collect::<Option<Vec<Source>>>()?;
+    Some((sources, query))
+}
+
+/// Checks if a text is a URL without a scheme, such as `youtu.be/xyz`.
+///
+/// The host should have a dot and an alphabetic top-level domain, and be followed by a path.
+fn is_schemeless_url(text: &str) -> bool {
+    if text.contains(char::is_whitespace) {
+        return false;
+    }
+    let Some((host, _)) = text.split_once('/') else {
+        return false;
+    };
+    let Some((name, tld)) = host.rsplit_once('.') else {
+        return false;
+    };
+    !name.is_empty() && tld.len() >= 2 && tld.chars().all(|c| c.is_ascii_alphabetic())
+}
+
+/// Returns the URL of a YouTube video ID.
+pub fn youtube_url(id: &str) -> String {
+    format!("https://www.youtube.com/watch?v={}", id)
+}
+
+/// Looks up a possible YouTube video ID, to tell it apart from a search query which looks like
+/// an ID.
+///
+/// # Parameters
+/// - `id`: The possible ID, classified as `MediaRef::YoutubeId`.
+/// - `offline`: Only check if the video is downloaded, instead of fetching it with `yt-dlp`.
+///
+/// # Returns
+/// - `Some(SearchResult)`: The video with the ID, with its metadata when it was fetched.
+/// - `None`: If there is no such video (or it isn't downloaded offline), so the text should be
+///   searched instead.
+pub fn youtube_video(id: &str, offline: bool) -> Option<SearchResult> {
+    let url = youtube_url(id);
+    let video = if offline {
+        download::find(&url).map(|_| SearchResult::from_url(&url))
+    } else {
+        youtube::metadata(&url).ok()
+    };
+    match video {
+        Some(_) => info(format!("Using '{}' as a YouTube video ID.", id)),
+        None if offline => info(format!(
+            "'{}' is not a downloaded YouTube video, searching it.",
+            id
+        )),
+        None => info(format!("'{}' is not a YouTube video ID, searching it.", id)),
+    }
+    video
+}
+
+/// Checks if a text looks like a YouTube video ID.
+///
+/// IDs are 11 characters of letters, digits, `-` and `_`. To not take words for IDs, the text
+/// should contain a digit, `-` or `_`, or an uppercase letter after the first character.
+fn is_youtube_id(text: &str) -> bool {
+    text.len() == YOUTUBE_ID_LENGTH
+        && text
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        && text
+            .chars()
+            .enumerate()
+            .any(|(index, c)| !c.is_ascii_alphabetic() || (index > 0 && c.is_ascii_uppercase()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(url: &str) -> MediaRef {
+        MediaRef::Url(url.to_string())
+    }
+
+    fn search(query: &str, sources: Option<&[Source]>) -> MediaRef {
+        MediaRef::Search {
+            query: query.to_string(),
+            sources: sources.map(<[Source]>::to_vec),
+        }
+    }
+
+    #[test]
+    fn classifies_media() {
+        let cases = [
+            ("https://youtu.be/x", url("https://youtu.be/x")),
+            ("youtu.be/x", url("https://youtu.be/x")),
+            (
+                "www.youtube.com/watch?v=dQw4w9WgXcQ",
+                url("https://www.youtube.com/watch?v=dQw4w9WgXcQ"),
+            ),
+            ("ytdl://ytsearch:lofi", url("ytdl://ytsearch:lofi")),
+            (
+                "rtmp://live.example/stream",
+                url("rtmp://live.example/stream"),
+            ),
+            (
+                " dQw4w9WgXcQ ",
+                MediaRef::YoutubeId(String::from("dQw4w9WgXcQ")),
+            ),
+            (
+                "a-b_c123456",
+                MediaRef::YoutubeId(String::from("a-b_c123456")),
+            ),
+            ("programming", search("programming", None)),
+            ("Programming", search("Programming", None)),
+            ("lofi hip hop", search("lofi hip hop", None)),
+            ("sc:query", search("query", Some(&[Source::Soundcloud]))),
+            (
+                "ytm,yt: some query",
+                search("some query", Some(&[Source::YoutubeMusic, Source::Youtube])),
+            ),
+            ("local:song", search("song", Some(&[Source::Local]))),
+            ("artist: song", search("artist: song", None)),
+            ("sc:", search("sc:", None)),
+            ("sc, yt:query", search("sc, yt:query", None)),
+            ("v1.2/3", search("v1.2/3", None)),
+            ("AC/DC", search("AC/DC", None)),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(MediaRef::classify(text), expected, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn classifies_local_files() {
+        let path = std::fs::canonicalize("src/main.rs")
+            .unwrap()
+            .display()
+            .to_string();
+        let expected = MediaRef::Local(vec![path.clone()]);
+        // An existing relative path isn't taken for a URL without a scheme
+        assert_eq!(MediaRef::classify("src/main.rs"), expected);
+        assert_eq!(MediaRef::classify(&path), expected);
+        assert_eq!(MediaRef::classify(&format!("file://{}", path)), expected);
+        // Directories without audio files have no files to play
+        assert_eq!(MediaRef::classify("src"), MediaRef::Local(Vec::new()));
+        assert_eq!(
+            MediaRef::classify("file:///playit/missing.mp3"),
+            url("file:///playit/missing.mp3")
+        );
+    }
+
+    #[test]
+    fn checks_youtube_ids() {
+        let cases = [
+            ("dQw4w9WgXcQ", true),
+            ("Radiohead12", true),
+            ("abcdefghij_", true),
+            ("helloWorldx", true),
+            ("programming", false),
+            ("Programming", false),
+            ("dQw4w9WgXc", false),
+            ("dQw4w9WgXcQQ", false),
+            ("dQw4w9WgXc!", false),
+            ("dQw4w9 gXcQ", false),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(is_youtube_id(text), expected, "{:?}", text);
+        }
+    }
+}
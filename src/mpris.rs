@@ -0,0 +1,471 @@
+use crate::ipc::MpvIpc;
+use crate::log::{error, info, warning};
+use crate::utils::is_url;
+use crate::youtube;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use zbus::blocking::{connection, Connection};
+use zbus::fdo;
+use zbus::interface;
+use zbus::zvariant::{ObjectPath, OwnedValue, Value as Variant};
+
+/// The well-known name of the playit MPRIS service.
+const BUS_NAME: &str = "org.mpris.MediaPlayer2.playit";
+
+/// The object path of MPRIS interfaces.
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+
+/// The name of the MPRIS player interface.
+const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+
+/// Properties of mpv which are observed for sending change signals.
+const OBSERVED_PROPERTIES: [&str; 5] = ["pause", "media-title", "duration", "volume", "path"];
+
+/// State shared by the MPRIS interfaces and the event thread.
+///
+/// # Fields
+/// - `mpv`: The IPC connection used for reading properties and sending commands.
+/// - `extra`: Metadata fetched with `yt-dlp`, keyed by media URL.
+struct State {
+    mpv: MpvIpc,
+    extra: HashMap<String, youtube::SearchResult>,
+}
+
+type SharedState = Arc<Mutex<State>>;
+
+/// The `org.mpris.MediaPlayer2` interface.
+struct Root {
+    state: SharedState,
+}
+
+/// The `org.mpris.MediaPlayer2.Player` interface.
+struct Player {
+    state: SharedState,
+}
+
+/// Runs a command on mpv, converting the error into a D-Bus error.
+fn command(state: &SharedState, args: Value) -> fdo::Result<Value> {
+    state
+        .lock()
+        .map_err(|_| fdo::Error::Failed("playit state is poisoned.".to_string()))?
+        .mpv
+        .command(args)
+        .map_err(fdo::Error::Failed)
+}
+
+/// Reads a property from mpv, returning `Value::Null` if it is not available.
+fn property(state: &SharedState, name: &str) -> Value {
+    command(state, json!(["get_property", name])).unwrap_or(Value::Null)
+}
+
+/// Returns the MPRIS playback status of mpv.
+fn playback_status(state: &SharedState) -> String {
+    if property(state, "path").is_null() {
+        "Stopped".to_string()
+    } else if property(state, "pause").as_bool().unwrap_or(false) {
+        "Paused".to_string()
+    } else {
+        "Playing".to_string()
+    }
+}
+
+/// Returns the MPRIS track id of the current media.
+fn track_id(state: &SharedState) -> String {
+    match property(state, "playlist-pos").as_u64() {
+        Some(index) => format!("/org/playit/track/{}", index),
+        None => "/org/mpris/MediaPlayer2/TrackList/NoTrack".to_string(),
+    }
+}
+
+/// Checks whether a loop property of mpv (`loop-file`, `loop-playlist`) is enabled.
+///
+/// mpv reports these as `false` when disabled, and as `"inf"`, `true` or a count otherwise.
+fn is_looping(value: &Value) -> bool {
+    match value {
+        Value::Bool(enabled) => *enabled,
+        Value::Number(count) => count.as_i64() != Some(0),
+        Value::String(mode) => mode != "no",
+        _ => false,
+    }
+}
+
+/// Builds the MPRIS metadata of the current media.
+fn metadata(state: &SharedState) -> HashMap<String, OwnedValue> {
+    let mut metadata: HashMap<String, Variant> = HashMap::new();
+    if let Ok(path) = ObjectPath::try_from(track_id(state)) {
+        metadata.insert("mpris:trackid".to_string(), Variant::from(path));
+    }
+
+    let url = property(state, "path").as_str().map(str::to_string);
+    let extra = url
+        .as_ref()
+        .and_then(|url| state.lock().ok()?.extra.get(url).cloned());
+    let title = extra
+        .as_ref()
+        .and_then(|extra| extra.title.clone())
+        .or_else(|| property(state, "media-title").as_str().map(str::to_string));
+
+    if let Some(title) = title {
+        metadata.insert("xesam:title".to_string(), Variant::from(title));
+    }
+    if let Some(url) = url {
+        metadata.insert("xesam:url".to_string(), Variant::from(url));
+    }
+    if let Some(duration) = property(state, "duration").as_f64() {
+        let length = (duration * 1_000_000.0) as i64;
+        metadata.insert("mpris:length".to_string(), Variant::from(length));
+    }
+    if let Some(extra) = extra {
+        if let Some(uploader) = extra.uploader {
+            metadata.insert("xesam:artist".to_string(), Variant::from(vec![uploader]));
+        }
+        if let Some(thumbnail) = extra.thumbnail {
+            metadata.insert("mpris:artUrl".to_string(), Variant::from(thumbnail));
+        }
+    }
+
+    metadata
+        .into_iter()
+        .filter_map(|(key, value)| Some((key, OwnedValue::try_from(value).ok()?)))
+        .collect()
+}
+
+#[interface(name = "org.mpris.MediaPlayer2")]
+impl Root {
+    fn raise(&self) {}
+
+    fn quit(&self) -> fdo::Result<()> {
+        command(&self.state, json!(["quit"])).map(|_| ())
+    }
+
+    #[zbus(property)]
+    fn can_quit(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_raise(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn has_track_list(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn identity(&self) -> String {
+        "playit".to_string()
+    }
+
+    #[zbus(property)]
+    fn supported_uri_schemes(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    #[zbus(property)]
+    fn supported_mime_types(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+#[interface(name = "org.mpris.MediaPlayer2.Player")]
+impl Player {
+    fn next(&self) -> fdo::Result<()> {
+        command(&self.state, json!(["playlist-next"])).map(|_| ())
+    }
+
+    fn previous(&self) -> fdo::Result<()> {
+        command(&self.state, json!(["playlist-prev"])).map(|_| ())
+    }
+
+    fn pause(&self) -> fdo::Result<()> {
+        command(&self.state, json!(["set_property", "pause", true])).map(|_| ())
+    }
+
+    fn play_pause(&self) -> fdo::Result<()> {
+        command(&self.state, json!(["cycle", "pause"])).map(|_| ())
+    }
+
+    fn stop(&self) -> fdo::Result<()> {
+        // mpv's `stop` would clear the queue of the daemon, so rewind and pause instead
+        command(&self.state, json!(["set_property", "pause", true]))?;
+        command(&self.state, json!(["seek", 0, "absolute"])).map(|_| ())
+    }
+
+    fn play(&self) -> fdo::Result<()> {
+        command(&self.state, json!(["set_property", "pause", false])).map(|_| ())
+    }
+
+    fn seek(&self, offset: i64) -> fdo::Result<()> {
+        let seconds = offset as f64 / 1_000_000.0;
+        command(&self.state, json!(["seek", seconds, "relative"])).map(|_| ())
+    }
+
+    fn set_position(&self, track_id: ObjectPath<'_>, position: i64) -> fdo::Result<()> {
+        // Ignore requests for a track which is not playing anymore, as the spec says
+        if track_id.as_str() != self::track_id(&self.state) {
+            return Ok(());
+        }
+        let seconds = position as f64 / 1_000_000.0;
+        command(&self.state, json!(["seek", seconds, "absolute"])).map(|_| ())
+    }
+
+    fn open_uri(&self, _uri: &str) -> fdo::Result<()> {
+        Err(fdo::Error::NotSupported(
+            "Use `playit --play` or `playit queue add` for playing media.".to_string(),
+        ))
+    }
+
+    #[zbus(property)]
+    fn playback_status(&self) -> String {
+        playback_status(&self.state)
+    }
+
+    #[zbus(property)]
+    fn loop_status(&self) -> String {
+        if is_looping(&property(&self.state, "loop-file")) {
+            "Track".to_string()
+        } else if is_looping(&property(&self.state, "loop-playlist")) {
+            "Playlist".to_string()
+        } else {
+            "None".to_string()
+        }
+    }
+
+    #[zbus(property)]
+    fn set_loop_status(&mut self, value: String) -> fdo::Result<()> {
+        let (loop_file, loop_playlist) = match value.as_str() {
+            "Track" => ("inf", "no"),
+            "Playlist" => ("no", "inf"),
+            _ => ("no", "no"),
+        };
+        command(&self.state, json!(["set_property", "loop-file", loop_file]))?;
+        command(
+            &self.state,
+            json!(["set_property", "loop-playlist", loop_playlist]),
+        )
+        .map(|_| ())
+    }
+
+    #[zbus(property)]
+    fn rate(&self) -> f64 {
+        property(&self.state, "speed").as_f64().unwrap_or(1.0)
+    }
+
+    #[zbus(property)]
+    fn set_rate(&mut self, value: f64) -> fdo::Result<()> {
+        command(&self.state, json!(["set_property", "speed", value])).map(|_| ())
+    }
+
+    #[zbus(property)]
+    fn metadata(&self) -> HashMap<String, OwnedValue> {
+        metadata(&self.state)
+    }
+
+    #[zbus(property)]
+    fn volume(&self) -> f64 {
+        property(&self.state, "volume").as_f64().unwrap_or(0.0) / 100.0
+    }
+
+    #[zbus(property)]
+    fn set_volume(&mut self, value: f64) -> fdo::Result<()> {
+        let volume = (value.max(0.0) * 100.0).round();
+        command(&self.state, json!(["set_property", "volume", volume])).map(|_| ())
+    }
+
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn position(&self) -> i64 {
+        let position = property(&self.state, "time-pos").as_f64().unwrap_or(0.0);
+        (position * 1_000_000.0) as i64
+    }
+
+    #[zbus(property)]
+    fn minimum_rate(&self) -> f64 {
+        0.01
+    }
+
+    #[zbus(property)]
+    fn maximum_rate(&self) -> f64 {
+        100.0
+    }
+
+    #[zbus(property)]
+    fn can_go_next(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_go_previous(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_play(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_pause(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_seek(&self) -> bool {
+        true
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn can_control(&self) -> bool {
+        true
+    }
+}
+
+/// Starts the MPRIS service for the mpv instance listening on `mpv_socket`.
+///
+/// The service runs on background threads; this function returns after the service is
+/// registered on the bus.
+///
+/// # Parameters
+/// - `mpv_socket`: The path of the mpv IPC socket.
+/// - `bus_address`: Address of the D-Bus bus to use instead of the session bus (e.g., a private
+///   bus started for testing).
+///
+/// # Returns
+/// - `Ok(())` if the service was registered.
+/// - `Err(String)` if the bus or mpv couldn't be reached.
+pub fn start(mpv_socket: &str, bus_address: Option<&str>) -> Result<(), String> {
+    let state: SharedState = Arc::new(Mutex::new(State {
+        mpv: MpvIpc::connect(mpv_socket)?,
+        extra: HashMap::new(),
+    }));
+
+    let builder = match bus_address {
+        Some(address) => connection::Builder::address(address),
+        None => connection::Builder::session(),
+    };
+    let connection = builder
+        .and_then(|builder| builder.name(BUS_NAME))
+        .and_then(|builder| {
+            builder.serve_at(
+                OBJECT_PATH,
+                Root {
+                    state: state.clone(),
+                },
+            )
+        })
+        .and_then(|builder| {
+            builder.serve_at(
+                OBJECT_PATH,
+                Player {
+                    state: state.clone(),
+                },
+            )
+        })
+        .and_then(|builder| builder.build())
+        .map_err(|e| format!("Unable to register MPRIS service: {}", e))?;
+
+    let mut events = MpvIpc::connect(mpv_socket)?;
+    events.set_timeout(None)?;
+    for (id, name) in OBSERVED_PROPERTIES.iter().enumerate() {
+        events.observe_property(id as u64, name)?;
+    }
+    std::thread::spawn(move || {
+        if let Err(e) = watch(events, connection, state) {
+            warning("MPRIS service stopped.");
+            error(&e);
+        }
+    });
+    info(format!("MPRIS service registered as: {}", BUS_NAME));
+    Ok(())
+}
+
+/// Sends MPRIS change signals for the events of mpv until the connection is closed.
+fn watch(mut events: MpvIpc, connection: Connection, state: SharedState) -> Result<(), String> {
+    loop {
+        let event = events.next_event()?;
+        match event["event"].as_str() {
+            Some("property-change") => match event["name"].as_str() {
+                Some("pause") => {
+                    let status = Variant::from(playback_status(&state));
+                    properties_changed(&connection, vec![("PlaybackStatus", status)]);
+                }
+                Some("volume") => {
+                    let volume = event["data"].as_f64().unwrap_or(0.0) / 100.0;
+                    properties_changed(&connection, vec![("Volume", Variant::from(volume))]);
+                }
+                Some("path") => {
+                    if let Some(url) = event["data"].as_str() {
+                        fetch_metadata(url, &connection, &state);
+                    }
+                    send_metadata(&connection, &state);
+                }
+                _ => send_metadata(&connection, &state),
+            },
+            Some("seek") | Some("playback-restart") => {
+                let position = property(&state, "time-pos").as_f64().unwrap_or(0.0);
+                let _ = connection.emit_signal(
+                    None::<&str>,
+                    OBJECT_PATH,
+                    PLAYER_INTERFACE,
+                    "Seeked",
+                    &((position * 1_000_000.0) as i64),
+                );
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Sends the metadata and the playback status of the current media.
+fn send_metadata(connection: &Connection, state: &SharedState) {
+    let metadata = Variant::from(metadata(state));
+    let status = Variant::from(playback_status(state));
+    properties_changed(
+        connection,
+        vec![("Metadata", metadata), ("PlaybackStatus", status)],
+    );
+}
+
+/// Fetches the artist and art URL of a media in background, and sends the metadata again when
+/// they are available.
+fn fetch_metadata(url: &str, connection: &Connection, state: &SharedState) {
+    let already_fetched = state
+        .lock()
+        .map(|state| state.extra.contains_key(url))
+        .unwrap_or(true);
+    if already_fetched || !is_url(url) {
+        return;
+    }
+
+    let (url, connection, state) = (url.to_string(), connection.clone(), state.clone());
+    std::thread::spawn(move || match youtube::metadata(&url) {
+        Ok(extra) => {
+            if let Ok(mut state) = state.lock() {
+                state.extra.insert(url, extra);
+            }
+            send_metadata(&connection, &state);
+        }
+        Err(e) => {
+            warning("Unable to fetch metadata for MPRIS.");
+            error(&e);
+        }
+    });
+}
+
+/// Emits the `PropertiesChanged` signal of the player interface.
+fn properties_changed(connection: &Connection, changed: Vec<(&str, Variant<'_>)>) {
+    let changed: HashMap<&str, Variant<'_>> = changed.into_iter().collect();
+    let invalidated: Vec<&str> = Vec::new();
+    if let Err(e) = connection.emit_signal(
+        None::<&str>,
+        OBJECT_PATH,
+        "org.freedesktop.DBus.Properties",
+        "PropertiesChanged",
+        &(PLAYER_INTERFACE, changed, invalidated),
+    ) {
+        warning("Unable to send MPRIS signal.");
+        error(&e);
+    }
+}
@@ -1,13 +1,77 @@
 use crate::log::{error, info};
-use std::collections::HashMap;
-use std::process::Command;
+use std::process::{Child, Command};
+use std::sync::OnceLock;
 
-/// Type alias for a collection of MPV command-line arguments.
-pub type MpvArgs = HashMap<String, Option<String>>;
+/// The mpv program set by user, if any.
+static PROGRAM: OnceLock<String> = OnceLock::new();
+
+/// Sets the path of the mpv program used for the rest of the run.
+///
+/// # Parameters
+/// - `path`: The path or name of the program.
+pub fn set_program(path: &str) {
+    let _ = PROGRAM.set(path.to_string());
+}
+
+/// Returns the path of the mpv program, `mpv` from `$PATH` by default.
+pub fn program() -> &'static str {
+    PROGRAM.get().map(String::as_str).unwrap_or("mpv")
+}
+
+/// Type alias for an ordered list of MPV command-line options and their optional values.
+pub type MpvArgs = Vec<(String, Option<String>)>;
+
+/// The mpv options which playit sets itself, and the playit options to use instead of them.
+const MANAGED_OPTIONS: &[(&str, &str)] = &[
+    ("video", "--show-video"),
+    ("vid", "--show-video"),
+    ("audio", "--only-video"),
+    ("aid", "--only-video"),
+    ("volume", "--volume"),
+    ("mute", "--mute"),
+    ("loop", "--loop"),
+    ("loop-file", "--loop"),
+    ("loop-playlist", "--loop"),
+    ("ytdl", "--offline"),
+    ("input-ipc-server", "--socket"),
+    ("idle", "playit daemon"),
+    ("playlist", "--playlist"),
+];
+
+/// Parses an mpv option given by user (e.g., `audio-device=pulse` or `no-border`).
+///
+/// # Parameters
+/// - `option`: The option in `key=value` or `key` form. A leading `--` is allowed.
+///
+/// # Returns
+/// - `Ok((String, Option<String>))`: The option in the form of `MpvArgs` (e.g., `--audio-device`
+///   and `pulse`).
+/// - `Err(String)`: An error message if the option is empty or it is managed by playit.
+pub fn parse_option(option: &str) -> Result<(String, Option<String>), String> {
+    let (key, value) = match option.split_once('=') {
+        Some((key, value)) => (key, Some(value.to_string())),
+        None => (option, None),
+    };
+    let key = key.trim().trim_start_matches("--");
+    if key.is_empty() {
+        return Err(format!(
+            "Invalid mpv option '{}', expected key=value.",
+            option
+        ));
+    }
+    let name = key.strip_prefix("no-").unwrap_or(key);
+    if let Some((_, instead)) = MANAGED_OPTIONS.iter().find(|(managed, _)| *managed == name) {
+        return Err(format!(
+            "mpv option '{}' is managed by playit, use {} instead.",
+            key, instead
+        ));
+    }
+    Ok((format!("--{}", key), value))
+}
 
 /// Represents an MPV instance with media and associated arguments.
 pub struct Mpv {
-    audio: String,         // The media URL or file path
+    media: Vec<String>,    // The media URLs or file paths, in playing order
     args: Option<MpvArgs>, // Optional MPV arguments
 }
 
@@ -15,24 +79,53 @@ impl Mpv {
     /// Creates a new `Mpv` instance.
     ///
     /// # Parameters
-    /// - `audio`: The media URL or file path to be played.
+    /// - `media`: The media URLs or file paths to be played, in order. The same media can be
+    ///   given more than once.
     /// - `args`: Optional MPV arguments (e.g., video settings).
     ///
     /// # Returns
     /// A new `Mpv` instance.
-    pub fn new(audio: String, args: Option<MpvArgs>) -> Self {
-        Mpv { audio, args }
+    pub fn new(media: Vec<String>, args: Option<MpvArgs>) -> Self {
+        Mpv { media, args }
+    }
+
+    /// Creates a new `Mpv` instance which waits for media to be loaded later (e.g., over IPC).
+    ///
+    /// # Parameters
+    /// - `args`: Optional MPV arguments (e.g., video settings).
+    ///
+    /// # Returns
+    /// A new `Mpv` instance without media.
+    pub fn idle(args: Option<MpvArgs>) -> Self {
+        let mut args = args.unwrap_or_default();
+        args.push(("--idle".to_string(), Some("yes".to_string())));
+        Mpv {
+            media: Vec::new(),
+            args: Some(args),
+        }
     }
 
-    /// Spawns the MPV player with the specified audio and arguments.
+    /// Spawns the MPV player with the specified media and arguments.
     ///
     /// # Returns
     /// - `u32`: The process ID of the spawned MPV process, or `0` if an error occurred.
     pub fn spawn(&self) -> u32 {
-        let mut command = Command::new("mpv");
+        match self.spawn_child() {
+            Ok(child) => child.id(),
+            Err(e) => {
+                error(e);
+                0
+            }
+        }
+    }
 
-        // Add the audio file or URL to the MPV command
-        command.arg(&self.audio);
+    /// Spawns the MPV player and returns the handle of the child process.
+    ///
+    /// # Returns
+    /// - `Ok(Child)`: The spawned MPV process.
+    /// - `Err(String)`: An error message if the process couldn't be spawned.
+    pub fn spawn_child(&self) -> Result<Child, String> {
+        let mut command = Command::new(program());
 
         // Add optional arguments if provided
         if let Some(args) = &self.args {
@@ -46,32 +139,40 @@ impl Mpv {
             }
         }
 
+        // Add the media after the options, so they are never mistaken for options
+        if !self.media.is_empty() {
+            command.arg("--");
+            command.args(&self.media);
+        }
+
         // Execute the command and handle errors if any
         match command.spawn() {
             Ok(child) => {
                 // Log the spawn details for debugging purposes
-                log_spawn_details(&self.audio, &self.args);
-                child.id()
-            }
-            Err(e) => {
-                error(format!(
-                    "An error occurred while spawning the MPV command: {}",
-                    e
-                ));
-                0
+                log_spawn_details(&self.media, &self.args);
+                Ok(child)
             }
+            Err(e) => Err(format!(
+                "An error occurred while spawning the MPV command: {}",
+                e
+            )),
         }
     }
 }
 
 /// Logs the details of the spawned MPV process for debugging purposes.
-fn log_spawn_details(audio: &str, args: &Option<MpvArgs>) {
+fn log_spawn_details(media: &[String], args: &Option<MpvArgs>) {
+    let media_str = match media {
+        [] => String::from("(idle)"),
+        [single] => single.to_string(),
+        [first, rest @ ..] => format!("{} (and {} more)", first, rest.len()),
+    };
     let args_str = match args {
         Some(arguments) => format!("{:?}", arguments),
         None => String::from("No arguments provided"),
     };
     info(format!(
-        "mpv spawn details:\n  Audio: {}\n  Arguments: {}",
-        audio, args_str
+        "mpv spawn details:\n  Media: {}\n  Arguments: {}",
+        media_str, args_str
     ));
 }
@@ -1,6 +1,29 @@
 use crate::log::{error, info, warning};
+use crate::utils::format_duration;
+use crate::youtube::SearchResult;
 use std::process::Command;
 
+/// Fills the placeholders of a notification text with the metadata of a media.
+///
+/// # Parameters
+/// - `template`: The notification text given by user.
+/// - `media`: The media which started playing.
+///
+/// # Returns
+/// The notification text with `{title}`, `{uploader}`, `{duration}`, `{url}` and `{}` replaced.
+/// Unknown values are replaced with an empty string, except the title which falls back to the URL.
+pub fn format_notification(template: &str, media: &SearchResult) -> String {
+    template
+        .replace("{title}", media.name())
+        .replace("{uploader}", media.uploader.as_deref().unwrap_or_default())
+        .replace(
+            "{duration}",
+            &media.duration.map(format_duration).unwrap_or_default(),
+        )
+        .replace("{url}", &media.url)
+        .replace("{}", &media.url)
+}
+
 /// Sends a desktop notification with the provided message.
 ///
 /// # Parameters
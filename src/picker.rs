@@ -0,0 +1,157 @@
+use crate::utils::format_duration;
+use crate::youtube::SearchResult;
+use std::io::{BufRead, Write};
+use std::process::{Command, Stdio};
+
+/// Lets the user choose one of the search results.
+///
+/// The results are shown on the terminal and the choice is read from the standard input, or
+/// they are piped to an external menu command (e.g., `dmenu`, `rofi -dmenu`, `fzf`) which prints
+/// the chosen line.
+///
+/// # Parameters
+/// - `results`: The search results to choose from.
+/// - `menu`: The menu command, run with `sh -c`. `None` for choosing on the terminal.
+///
+/// # Returns
+/// - `Ok(usize)`: The index of the chosen result.
+/// - `Err(String)`: An error message if nothing valid was chosen.
+pub fn pick(results: &[SearchResult], menu: Option<&str>) -> Result<usize, String> {
+    let lines: Vec<String> = results
+        .iter()
+        .enumerate()
+        .map(|(index, result)| format!("{}. {}", index + 1, describe(result)))
+        .collect();
+
+    let prompt = format!("Choose [1-{}] (default 1): ", lines.len());
+    let choice = match menu {
+        Some(menu) => run_menu(menu, &lines)?,
+        None => ask(&lines, &prompt)?,
+    };
+
+    // Both the number and the whole line are accepted
+    let number = choice.split('.').next().unwrap_or_default().trim();
+    match number.parse::<usize>() {
+        Ok(number) if number >= 1 && number <= results.len() => Ok(number - 1),
+        _ => Err(format!("'{}' is not a valid choice.", choice)),
+    }
+}
+
+/// Offers the cached results of queries close to a query, before searching it.
+///
+/// The matches are shown like `pick` shows the search results, with an extra choice for
+/// searching the query instead.
+///
+/// # Parameters
+/// - `query`: The query which is not cached.
+/// - `matches`: The close queries with their cached results, most similar first.
+/// - `menu`: The menu command, run with `sh -c`. `None` for choosing on the terminal.
+///
+/// # Returns
+/// - `Ok(Some(usize))`: The index of the chosen match.
+/// - `Ok(None)`: If searching the query was chosen.
+/// - `Err(String)`: An error message if nothing valid was chosen.
+pub fn offer(
+    query: &str,
+    matches: &[(String, SearchResult)],
+    menu: Option<&str>,
+) -> Result<Option<usize>, String> {
+    let mut lines: Vec<String> = matches
+        .iter()
+        .enumerate()
+        .map(|(index, (similar, result))| {
+            format!("{}. {} ({})", index + 1, describe(result), similar)
+        })
+        .collect();
+    lines.push(format!("0. Search for '{}'", query));
+
+    let choice = match menu {
+        Some(menu) => run_menu(menu, &lines)?,
+        None => {
+            println!("'{}' is not cached, but similar queries are:", query);
+            ask(
+                &lines,
+                &format!("Choose [0-{}] (default 1): ", matches.len()),
+            )?
+        }
+    };
+
+    let number = choice.split('.').next().unwrap_or_default().trim();
+    match number.parse::<usize>() {
+        Ok(0) => Ok(None),
+        Ok(number) if number <= matches.len() => Ok(Some(number - 1)),
+        _ => Err(format!("'{}' is not a valid choice.", choice)),
+    }
+}
+
+/// Describes a search result in a single line.
+fn describe(result: &SearchResult) -> String {
+    let mut line = result.name().to_string();
+    if let Some(ref uploader) = result.uploader {
+        line.push_str(&format!(" - {}", uploader));
+    }
+    if result.is_live {
+        line.push_str(" [live]");
+    } else if let Some(duration) = result.duration {
+        line.push_str(&format!(" [{}]", format_duration(duration)));
+    }
+    if let Some(views) = result.views {
+        line.push_str(&format!(" ({} views)", format_views(views)));
+    }
+    line
+}
+
+/// Formats a view count in a short form (e.g., `1.2M`).
+fn format_views(views: u64) -> String {
+    match views {
+        0..1_000 => views.to_string(),
+        1_000..1_000_000 => format!("{:.1}K", views as f64 / 1_000.0),
+        1_000_000..1_000_000_000 => format!("{:.1}M", views as f64 / 1_000_000.0),
+        _ => format!("{:.1}B", views as f64 / 1_000_000_000.0),
+    }
+}
+
+/// Prints the lines and reads the choice from the terminal. An empty answer chooses the first.
+fn ask(lines: &[String], prompt: &str) -> Result<String, String> {
+    for line in lines {
+        println!("{}", line);
+    }
+    print!("{}", prompt);
+    std::io::stdout()
+        .flush()
+        .map_err(|e| format!("Unable to print results: {}", e))?;
+
+    let mut answer = String::new();
+    std::io::stdin()
+        .lock()
+        .read_line(&mut answer)
+        .map_err(|e| format!("Unable to read the choice: {}", e))?;
+    let answer = answer.trim();
+    Ok(if answer.is_empty() { "1" } else { answer }.to_string())
+}
+
+/// Pipes the lines to the menu command and returns the line it printed.
+fn run_menu(menu: &str, lines: &[String]) -> Result<String, String> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(menu)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Unable to run menu command '{}': {}", menu, e))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(lines.join("\n").as_bytes())
+            .map_err(|e| format!("Unable to send results to menu: {}", e))?;
+    }
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("Unable to read the choice from menu: {}", e))?;
+
+    let choice = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() || choice.is_empty() {
+        return Err("Nothing was chosen.".to_string());
+    }
+    Ok(choice)
+}
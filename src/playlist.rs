@@ -1,21 +1,206 @@
+use crate::download;
 use crate::log::{error, info, warning};
-use crate::utils::is_url;
-use crate::youtube::search;
+use crate::media::{youtube_url, youtube_video, MediaRef};
+use crate::quality::StreamFormat;
+use crate::search::Searcher;
+use crate::storage::{self, FileLock};
+use crate::utils::{is_url, now};
+use crate::youtube::{self, metadata, SearchResult};
 use dirs::config_dir;
+use serde::{Deserialize, Serialize};
 use serde_json;
+use std::collections::HashMap;
 use std::fs;
+use std::path::Path;
 
 /// Directory path for storing playlist files.
 const PLAYLISTS_DIR: &str = "{}/playit";
 
-/// Represents a playlist with a path to the file and a list of items (URLs).
+/// Version of the playlist file format written by playit.
+const PLAYLIST_VERSION: u32 = 2;
+
+/// Represents a media item of a playlist.
+///
+/// # Fields
+/// - `query`: The query (or URL) given by user when adding the item.
+/// - `url`: The resolved URL of the media.
+/// - `title`: The title of the media.
+/// - `channel`: The channel or artist who uploaded the media.
+/// - `duration`: The duration of the media in seconds.
+/// - `added_at`: The UNIX timestamp of when the item was added.
+/// - `tags`: Tags given by user for the item.
+/// - `local_path`: The path of the downloaded file of the media, if it was downloaded.
+/// - `local_format`: The quality and the audio format of the downloaded file (e.g., `low, opus`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaylistItem {
+    pub query: String,
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub added_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local_path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local_format: Option<String>,
+}
+
+impl PlaylistItem {
+    /// Creates an item with only the URL known, as stored by the first playlist format.
+    fn from_url(url: String) -> Self {
+        PlaylistItem {
+            query: url.clone(),
+            url,
+            title: None,
+            channel: None,
+            duration: None,
+            added_at: None,
+            tags: Vec::new(),
+            local_path: None,
+            local_format: None,
+        }
+    }
+
+    /// Creates an item from a search result, keeping its metadata.
+    fn from_result(result: SearchResult) -> Self {
+        PlaylistItem {
+            query: result.url.clone(),
+            url: result.url,
+            title: result.title,
+            channel: result.uploader,
+            duration: result.duration,
+            added_at: Some(now()),
+            tags: Vec::new(),
+            local_path: None,
+            local_format: None,
+        }
+    }
+
+    /// Returns the title of the item, or the URL if the title is unknown.
+    pub fn name(&self) -> &str {
+        self.title.as_deref().unwrap_or(&self.url)
+    }
+
+    /// Returns the path of the downloaded file of the item if it still exists, or the URL if
+    /// the item is a local file.
+    ///
+    /// The download directory is searched when the recorded file is gone (e.g., it was
+    /// downloaded again in another format for another playlist).
+    pub fn local_file(&self) -> Option<String> {
+        if let Some(path) = self.local_path.as_deref() {
+            if Path::new(path).exists() {
+                return Some(path.to_string());
+            }
+            return download::find(&self.url).map(|download| download.path);
+        }
+        Some(self.url.clone()).filter(|url| !is_url(url) && Path::new(url).exists())
+    }
+}
+
+/// The content of a playlist file.
+#[derive(Serialize, Deserialize)]
+struct PlaylistFile {
+    version: u32,
+    items: Vec<PlaylistItem>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    sources: Vec<String>,
+}
+
+/// The playlist file formats which can be read.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PlaylistContent {
+    /// The current format, with metadata for every item.
+    Versioned(PlaylistFile),
+    /// The first format, which is an array of URLs.
+    Legacy(Vec<String>),
+}
+
+/// Returns the directory of playlist files, creating the default one if it doesn't exist.
+///
+/// # Parameters
+/// - `prefix`: The custom playlist directory given with `--prefix`, if any.
+///
+/// # Returns
+/// A string containing `prefix`, or `$XDG_CONFIG_HOME/playit` if no prefix is given.
+pub fn directory(prefix: Option<&str>) -> String {
+    if let Some(p) = prefix {
+        return p.to_string();
+    }
+    let dir = PLAYLISTS_DIR.replace(
+        "{}",
+        &config_dir()
+            .unwrap_or_else(|| {
+                // Added `--prefix` flag.
+                // Don't need $HOME/.config fallback
+                error("Unable to retrieve the configuration directory");
+                std::process::exit(1);
+            })
+            .display()
+            .to_string(),
+    );
+    if !fs::exists(&dir).unwrap_or(false) {
+        info("Creating default directory for playlists");
+        fs::create_dir(&dir).unwrap_or_else(|_| {
+            error("Unable to create default directory for playlists");
+            error("Consider using `--prefix` flag");
+            error("Or create the directory manually: '~/.config/playit'");
+            std::process::exit(1);
+        });
+    }
+    dir
+}
+
+/// Returns the path of the file of a playlist.
+///
+/// # Parameters
+/// - `name`: The name of the playlist.
+/// - `prefix`: The custom playlist directory given with `--prefix`, if any.
+fn file_path(name: &str, prefix: Option<&str>) -> String {
+    format!("{}/{}.pl", directory(prefix), name)
+}
+
+/// Returns the names of all playlists in the playlist directory, sorted alphabetically.
+///
+/// # Parameters
+/// - `prefix`: The custom playlist directory given with `--prefix`, if any.
+///
+/// # Returns
+/// - `Ok(Vec<String>)`: The names of playlists (without the `.pl` extension).
+/// - `Err(String)`: An error message if the directory couldn't be read.
+pub fn names(prefix: Option<&str>) -> Result<Vec<String>, String> {
+    let dir = directory(prefix);
+    let entries = fs::read_dir(&dir)
+        .map_err(|e| format!("Unable to read playlist directory {}: {}", dir, e))?;
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            file_name.strip_suffix(".pl").map(str::to_string)
+        })
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+/// Represents a playlist with a path to the file and a list of items.
 ///
 /// # Fields
 /// - `path`: A string representing the path to the playlist file.
-/// - `items`: A vector of strings containing the URLs of the playlist items.
+/// - `items`: A vector containing the media items of the playlist.
+/// - `sources`: URLs of YouTube playlists or channels imported into the playlist, used for syncing.
+/// - `lock`: The lock of the playlist file, held from reading the playlist until it is dropped.
 pub struct Playlist {
     pub path: String,
-    pub items: Vec<String>,
+    pub items: Vec<PlaylistItem>,
+    pub sources: Vec<String>,
+    lock: Option<FileLock>,
 }
 
 impl Playlist {
@@ -28,135 +213,295 @@ impl Playlist {
     /// # Returns
     /// A new `Playlist` instance with the generated file path and an empty list of items.
     pub fn new(name: &str, prefix: Option<&str>) -> Self {
-        // Construct the path for the playlist file based on the user's configuration directory.
-        let path = match prefix {
-            Some(p) => {
-                format!("{}/{}.pl", p, name)
-            }
-            None => {
-                let dir = PLAYLISTS_DIR.replace(
-                    "{}",
-                    &config_dir()
-                        .unwrap_or_else(|| {
-                            // Added `--prefix` flag.
-                            // Don't need $HOME/.config fallback
-                            error("Unable to retrieve the configuration directory");
-                            std::process::exit(1);
-                        })
-                        .display()
-                        .to_string(),
-                );
-                if !fs::exists(&dir).unwrap_or(false) {
-                    info("Creating default directory for playlists");
-                    fs::create_dir(&dir).unwrap_or_else(|_| {
-                        error("Unable to create default directory for playlists");
-                        error("Consider using `--prefix` flag");
-                        error("Or create the directory manually: '~/.config/playit'");
-                        std::process::exit(1);
-                    });
-                }
-                format!("{}/{}.pl", &dir, &name)
-            }
-        };
+        // Construct the path for the playlist file based on the playlist directory.
+        let path = file_path(name, prefix);
 
         // Log the creation of the new playlist
         info(format!("Creating new playlist: {}", name));
         Playlist {
             path,
             items: Vec::new(),
+            sources: Vec::new(),
+            lock: None,
+        }
+    }
+
+    /// Loads an existing playlist with the specified name.
+    ///
+    /// # Parameters
+    /// - `name`: The name of the playlist.
+    /// - `prefix`: The custom playlist directory, if any.
+    ///
+    /// # Returns
+    /// - `Ok(Playlist)`: The playlist with its items.
+    /// - `Err(String)`: An error message if the playlist doesn't exist or couldn't be read.
+    pub fn load(name: &str, prefix: Option<&str>) -> Result<Self, String> {
+        let mut playlist = Playlist {
+            path: file_path(name, prefix),
+            items: Vec::new(),
+            sources: Vec::new(),
+            lock: None,
+        };
+        if !fs::exists(&playlist.path).unwrap_or(false) {
+            return Err(format!("Playlist '{}' doesn't exist.", name));
+        }
+        playlist.read()?;
+        Ok(playlist)
+    }
+
+    /// Opens a playlist with the specified name, reading it if it exists.
+    ///
+    /// The playlist file is locked before checking if it exists, so playlists created by other
+    /// playit processes at the same time are not overwritten.
+    ///
+    /// # Parameters
+    /// - `name`: The name of the playlist.
+    /// - `prefix`: The custom playlist directory, if any.
+    ///
+    /// # Returns
+    /// - `Ok(Playlist)`: The playlist with its items, or an empty playlist if it doesn't exist.
+    /// - `Err(String)`: An error message if the playlist couldn't be read.
+    pub fn open(name: &str, prefix: Option<&str>) -> Result<Self, String> {
+        let mut playlist = Playlist {
+            path: file_path(name, prefix),
+            items: Vec::new(),
+            sources: Vec::new(),
+            lock: None,
+        };
+        playlist.reread()?;
+        if !fs::exists(&playlist.path).unwrap_or(false) {
+            info(format!("Creating new playlist: {}", name));
+        }
+        Ok(playlist)
+    }
+
+    /// Locks the playlist file and reads it again, if it exists, so the changes of other playit
+    /// processes made while the playlist was unlocked are kept.
+    ///
+    /// # Returns
+    /// - `Ok(())` if the playlist was read, or it doesn't exist (anymore).
+    /// - `Err(String)` if the playlist couldn't be read.
+    fn reread(&mut self) -> Result<(), String> {
+        self.lock();
+        if fs::exists(&self.path).unwrap_or(false) {
+            self.read()
+        } else {
+            self.items.clear();
+            self.sources.clear();
+            Ok(())
+        }
+    }
+
+    /// Releases the lock of the playlist file, so other playit processes don't wait while the
+    /// playlist is busy with the network. The playlist should be read again before changing it.
+    fn unlock(&mut self) {
+        self.lock = None;
+    }
+
+    /// Locks the playlist file until the playlist is dropped, if it isn't locked yet.
+    fn lock(&mut self) {
+        if self.lock.is_some() {
+            return;
+        }
+        match storage::lock(&self.path) {
+            Ok(lock) => self.lock = Some(lock),
+            Err(e) => {
+                warning("Unable to lock the playlist file, changes of other playit processes may be lost.");
+                error(&e);
+            }
         }
     }
 
-    /// Reads the playlist file and loads its content into the `items` field as a vector of strings.
+    /// Reads the playlist file and loads its content into the `items` field.
+    ///
+    /// Playlists in the first format (an array of URLs) are converted to the current format, and
+    /// they are saved in the current format on the next write.
+    ///
+    /// The playlist file is locked until the playlist is dropped, so other playit processes wait
+    /// for the changes to be written. If the file is corrupted, its last good copy is restored.
     ///
     /// # Returns
     /// - `Ok(())` if the file was successfully read and parsed.
     /// - `Err(String)` if there was an error reading or parsing the file.
     pub fn read(&mut self) -> Result<(), String> {
-        // Attempt to read the playlist file into a string.
-        let content = match fs::read_to_string(&self.path) {
-            Ok(content) => content,
-            Err(_) => {
-                error(format!(
-                    "Failed to read the playlist file at: {}",
-                    self.path
-                ));
-                return Err("Error reading the playlist file.".to_string());
-            }
-        };
+        self.lock();
 
-        // Deserialize the content into a vector of strings (URLs).
-        match serde_json::from_str::<Vec<String>>(&content) {
-            Ok(data) => {
-                self.items = data;
+        // Read and deserialize the content into playlist items.
+        match storage::read::<PlaylistContent>(&self.path) {
+            Ok(PlaylistContent::Versioned(data)) => {
+                if data.version > PLAYLIST_VERSION {
+                    warning("The playlist is written by a newer playit, some fields may be lost.");
+                }
+                self.items = data.items;
+                self.sources = data.sources;
                 info(format!("Playlist loaded successfully from: {}", self.path)); // Log successful loading
                 Ok(())
             }
-            Err(_) => {
-                error(format!(
-                    "Failed to parse the playlist JSON from: {}",
-                    self.path
-                ));
+            Ok(PlaylistContent::Legacy(data)) => {
+                self.items = data.into_iter().map(PlaylistItem::from_url).collect();
+                info(format!("Playlist loaded successfully from: {}", self.path)); // Log successful loading
+                info("The playlist will be upgraded to the new format on the next change.");
+                Ok(())
+            }
+            Err(e) => {
+                error(e);
                 Err("Failed to parse the playlist JSON. It might be corrupted.".to_string())
             }
         }
     }
 
-    /// Adds a new URL or search query to the playlist. If the query is not a valid URL,
-    /// a search is performed to find the appropriate URL.
+    /// Resolves a URL, local path or search query into the items to add to a playlist. If the
+    /// query is not a valid URL or path, a search is performed to find the appropriate URL.
+    ///
+    /// The title, channel and duration of the media are fetched with `yt-dlp` and saved with it.
+    /// Directories are resolved into all of their audio files.
+    ///
+    /// The playlist doesn't need to be open, so it isn't locked while searching.
     ///
     /// # Parameters
-    /// - `query`: A string slice containing either a URL or a search query.
+    /// - `query`: A string slice containing either a URL, a path or a search query.
+    /// - `tags`: Tags to save with the items.
+    /// - `searcher`: The searcher used for search queries.
     ///
     /// # Returns
-    /// This function does not return a value. It updates the playlist items.
-    pub fn add(&mut self, query: &str) {
+    /// The items to add, or no items if nothing was found for the query.
+    pub fn resolve(query: &str, tags: &[String], searcher: &Searcher) -> Vec<PlaylistItem> {
         if query.is_empty() {
             warning("An empty query given to add to playlist. Ignoring.");
-            return;
+            return Vec::new();
         }
-        // If the query is not a valid URL, perform a search.
-        let url = if is_url(query) {
-            query.to_string() // Convert &str to String
-        } else {
-            match search(query) {
-                Ok(result) => result,
-                Err(_) => {
+        let search = |searcher: &Searcher, text: &str| {
+            searcher
+                .search(text)
+                .inspect_err(|_| {
                     warning(format!(
                         "Search query '{}' did not return a valid result.",
                         query
                     )); // Log a warning for failed search
-                    return;
+                })
+                .ok()
+        };
+        let result = match MediaRef::classify(query) {
+            MediaRef::Local(files) => {
+                if files.is_empty() {
+                    warning(format!("No audio files found in '{}'. Ignoring.", query));
+                }
+                return files
+                    .iter()
+                    .map(|file| {
+                        let mut item = PlaylistItem::from_result(SearchResult::from_path(file));
+                        item.tags = tags.to_vec();
+                        item
+                    })
+                    .collect();
+            }
+            // The search result contains the metadata, but it has to be fetched for URLs (except
+            // in offline mode).
+            MediaRef::Url(url) if searcher.is_offline() => SearchResult::from_url(&url),
+            MediaRef::Url(url) => metadata(&url).unwrap_or_else(|e| {
+                warning("Unable to fetch the metadata of media. It will be added without it.");
+                error(&e);
+                SearchResult::from_url(&url)
+            }),
+            MediaRef::YoutubeId(id) => {
+                match youtube_video(&id, searcher.is_offline()).or_else(|| search(searcher, &id)) {
+                    Some(result) => result,
+                    None => return Vec::new(),
+                }
+            }
+            MediaRef::Search {
+                query: search_query,
+                sources,
+            } => {
+                let custom = sources.map(|sources| searcher.with_sources(&sources));
+                match search(custom.as_ref().unwrap_or(searcher), &search_query) {
+                    Some(result) => result,
+                    None => return Vec::new(),
                 }
             }
         };
 
-        // Add the valid URL to the playlist.
-        self.items.push(url);
-        info(format!("Added URL to playlist: {}", query)); // Log added URL
+        let mut item = PlaylistItem::from_result(result);
+        item.query = query.to_string();
+        item.tags = tags.to_vec();
+        vec![item]
     }
 
-    /// Removes a URL from the playlist based on an exact match.
+    /// Adds the items resolved for a query to the end of the playlist.
     ///
     /// # Parameters
-    /// - `query`: The URL to remove from the playlist.
+    /// - `query`: The query given by user, for logging.
+    /// - `items`: The items resolved with `Playlist::resolve`.
+    pub fn add(&mut self, query: &str, items: Vec<PlaylistItem>) {
+        match items.len() {
+            0 => return,
+            1 => info(format!("Added URL to playlist: {}", query)),
+            count => info(format!(
+                "Added {} local files to playlist: {}",
+                count, query
+            )),
+        }
+        self.items.extend(items);
+    }
+
+    /// Removes an item from the playlist based on an exact match.
+    ///
+    /// The item is searched by the query it was added with and by its URL first. If nothing
+    /// matches, the query is searched and the item with the resulting URL is removed. The
+    /// playlist is unlocked while searching, and read again after it.
+    ///
+    /// # Parameters
+    /// - `query`: The query or URL of the item to remove from the playlist.
+    /// - `searcher`: The searcher used when no item matches the query.
     ///
     /// # Returns
-    /// This function does not return a value. If the URL is found, it is removed from the playlist.
-    pub fn remove(&mut self, query: &str) {
+    /// - `Ok(())` if the playlist was updated, even if nothing was removed.
+    /// - `Err(String)` if the playlist couldn't be read again after searching.
+    pub fn remove(&mut self, query: &str, searcher: &Searcher) -> Result<(), String> {
         if query.is_empty() {
             warning("An empty query given to remove from playlist. Ignoring.");
-            return;
+            return Ok(());
         }
 
         // Find the index of the item that matches the query, and remove it if found.
-        if let Some(index) = self.items.iter().position(|item| {
-            item == &search(query).unwrap_or_else(|_| {
-                error("Unable to get video url for query in delete operation.");
-                error("The playlist will play, but the media will stay.");
-                String::new()
-            })
-        }) {
+        let mut position = self
+            .items
+            .iter()
+            .position(|item| item.query == query || item.url == query);
+        // An empty playlist has nothing to remove, so it isn't searched
+        if position.is_none() && !self.items.is_empty() {
+            self.unlock();
+            let url = match MediaRef::classify(query) {
+                MediaRef::Url(url) => url,
+                // Items are added with the URLs of video IDs, or with their queries otherwise
+                MediaRef::YoutubeId(id) => youtube_url(&id),
+                // Local files are saved with their absolute paths
+                MediaRef::Local(files) => files.into_iter().next().unwrap_or_default(),
+                MediaRef::Search {
+                    query: search_query,
+                    sources,
+                } => {
+                    let custom = sources.map(|sources| searcher.with_sources(&sources));
+                    custom
+                        .as_ref()
+                        .unwrap_or(searcher)
+                        .search(&search_query)
+                        .map(|result| result.url)
+                        .unwrap_or_else(|_| {
+                            error("Unable to get video url for query in delete operation.");
+                            error("The playlist will play, but the media will stay.");
+                            String::new()
+                        })
+                }
+            };
+            // Other playit processes may have changed the playlist while searching
+            self.reread()?;
+            position = self
+                .items
+                .iter()
+                .position(|item| item.query == query || item.url == url);
+        }
+        if let Some(index) = position {
             self.items.remove(index);
             info(format!("Removed URL from playlist: {}", query)); // Log URL removal
         } else {
@@ -165,6 +510,7 @@ impl Playlist {
                 query
             )); // Log a warning for non-existent URL
         }
+        Ok(())
     }
 
     /// Writes the playlist items to the playlist file as a JSON string.
@@ -174,7 +520,12 @@ impl Playlist {
     /// - `Err(String)` if there was an error writing to the file.
     pub fn write(self) -> Result<Self, String> {
         // Serialize the playlist items into a JSON string.
-        let content = match serde_json::to_string(&self.items) {
+        let file = PlaylistFile {
+            version: PLAYLIST_VERSION,
+            items: self.items,
+            sources: self.sources,
+        };
+        let content = match serde_json::to_string(&file) {
             Ok(content) => content,
             Err(_) => {
                 error("Failed to convert playlist items to string for writing to file.");
@@ -182,19 +533,385 @@ impl Playlist {
             }
         };
 
+        let playlist = Playlist {
+            path: self.path,
+            items: file.items,
+            sources: file.sources,
+            lock: self.lock,
+        };
+
+        // Lock the playlist file while writing if it wasn't read (and locked) before
+        let _lock = match playlist.lock {
+            Some(_) => None,
+            None => storage::lock(&playlist.path).ok(),
+        };
+
         // Write the content to the playlist file.
-        match fs::write(&self.path, content) {
+        match storage::write(&playlist.path, &content) {
             Ok(_) => {
-                info(format!("Playlist successfully written to: {}", self.path)); // Log successful write
-                Ok(self)
+                info(format!(
+                    "Playlist successfully written to: {}",
+                    playlist.path
+                )); // Log successful write
+                Ok(playlist)
             }
             Err(e) => {
-                error(format!(
-                    "Error writing to playlist file at {}: {}",
-                    self.path, e
-                )); // Log error during write
+                error(e); // Log error during write
                 Err("Failed to write playlist to file.".to_string())
             }
         }
     }
+
+    /// Returns the playlist items as media with their metadata, in order. The downloaded files
+    /// are played instead of the URLs.
+    ///
+    /// # Parameters
+    /// - `offline`: Leave out the items which are not downloaded.
+    pub fn results(&self, offline: bool) -> Vec<SearchResult> {
+        self.items
+            .iter()
+            .filter_map(|item| {
+                let url = match item.local_file() {
+                    Some(path) => path,
+                    None if offline => return None,
+                    None => item.url.clone(),
+                };
+                Some(SearchResult {
+                    url,
+                    title: item.title.clone(),
+                    uploader: item.channel.clone(),
+                    duration: item.duration,
+                    ..Default::default()
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the total duration of the items with a known duration, in seconds.
+    pub fn total_duration(&self) -> f64 {
+        self.items.iter().filter_map(|item| item.duration).sum()
+    }
+
+    /// Moves the playlist file to a new name.
+    ///
+    /// # Parameters
+    /// - `name`: The new name of the playlist.
+    /// - `prefix`: The custom playlist directory, if any.
+    ///
+    /// # Returns
+    /// - `Ok(())` if the playlist was renamed.
+    /// - `Err(String)` if a playlist with the new name exists or the file couldn't be moved.
+    pub fn rename(&mut self, name: &str, prefix: Option<&str>) -> Result<(), String> {
+        let target = file_path(name, prefix);
+        // The playlist itself is locked already
+        if target == self.path {
+            return Err(format!("Playlist '{}' already exists.", name));
+        }
+        // The new name is locked first, so a playlist created with it meanwhile isn't replaced
+        let lock = storage::lock(&target)?;
+        if fs::exists(&target).unwrap_or(false) {
+            return Err(format!("Playlist '{}' already exists.", name));
+        }
+        fs::rename(&self.path, &target)
+            .map_err(|e| format!("Unable to rename playlist file: {}", e))?;
+        // The backup belongs to the new name too
+        let _ = fs::rename(
+            storage::backup_path(&self.path),
+            storage::backup_path(&target),
+        );
+        self.lock();
+        storage::remove_lock(&self.path);
+        info(format!("Playlist renamed to: {}", target));
+        self.path = target;
+        self.lock = Some(lock);
+        Ok(())
+    }
+
+    /// Copies the playlist file to a new name.
+    ///
+    /// # Parameters
+    /// - `name`: The name of the copy.
+    /// - `prefix`: The custom playlist directory, if any.
+    ///
+    /// # Returns
+    /// - `Ok(Playlist)`: The copy of the playlist.
+    /// - `Err(String)`: An error message if a playlist with the new name exists or the file
+    ///   couldn't be copied.
+    pub fn copy(&self, name: &str, prefix: Option<&str>) -> Result<Playlist, String> {
+        let path = file_path(name, prefix);
+        // The playlist itself is locked already
+        if path == self.path {
+            return Err(format!("Playlist '{}' already exists.", name));
+        }
+        // The new name is locked first, so a playlist created with it meanwhile isn't replaced
+        let lock = storage::lock(&path)?;
+        if fs::exists(&path).unwrap_or(false) {
+            return Err(format!("Playlist '{}' already exists.", name));
+        }
+        fs::copy(&self.path, &path).map_err(|e| format!("Unable to copy playlist file: {}", e))?;
+        info(format!("Playlist copied to: {}", path));
+        Ok(Playlist {
+            path,
+            items: self.items.clone(),
+            sources: self.sources.clone(),
+            lock: Some(lock),
+        })
+    }
+
+    /// Deletes the playlist file.
+    ///
+    /// # Returns
+    /// - `Ok(())` if the playlist was deleted.
+    /// - `Err(String)` if the file couldn't be removed.
+    pub fn delete(mut self) -> Result<(), String> {
+        self.lock();
+        fs::remove_file(&self.path)
+            .map_err(|e| format!("Unable to delete playlist file: {}", e))?;
+        storage::remove_backup(&self.path);
+        storage::remove_lock(&self.path);
+        info(format!("Playlist deleted: {}", self.path));
+        Ok(())
+    }
+
+    /// Appends every video of a YouTube playlist or channel to the playlist.
+    ///
+    /// Videos which are already in the playlist are skipped, and the URL is saved as a source
+    /// of the playlist so it can be synced later. The playlist is unlocked while fetching the
+    /// videos, and read again after it.
+    ///
+    /// # Parameters
+    /// - `url`: The URL of the YouTube playlist or channel.
+    ///
+    /// # Returns
+    /// - `Ok(usize)`: The count of added items.
+    /// - `Err(String)`: An error message if the playlist couldn't be fetched or read.
+    pub fn import_url(&mut self, url: &str) -> Result<usize, String> {
+        self.unlock();
+        let entries = youtube::playlist_entries(url)?;
+        self.reread()?;
+        Ok(self.import_entries(url, entries))
+    }
+
+    /// Appends the fetched videos of a YouTube playlist or channel to the playlist, skipping the
+    /// videos which are already in it, and saves the URL as a source of the playlist.
+    ///
+    /// # Parameters
+    /// - `url`: The URL of the YouTube playlist or channel.
+    /// - `entries`: The videos of the playlist or channel.
+    ///
+    /// # Returns
+    /// The count of added items.
+    fn import_entries(&mut self, url: &str, entries: Vec<SearchResult>) -> usize {
+        let mut added = 0;
+        for entry in entries {
+            if self.items.iter().any(|item| item.url == entry.url) {
+                continue;
+            }
+            self.items.push(PlaylistItem::from_result(entry));
+            added += 1;
+        }
+
+        if !self.sources.iter().any(|source| source == url) {
+            self.sources.push(url.to_string());
+        }
+        info(format!("Added {} items from: {}", added, url));
+        added
+    }
+
+    /// Downloads the audio of the items which are not downloaded yet, or were downloaded in
+    /// another format, and records the paths and the formats of the files.
+    ///
+    /// Items which couldn't be downloaded are skipped with a warning. The playlist is unlocked
+    /// while downloading, and read again before recording the files.
+    ///
+    /// # Parameters
+    /// - `format`: The quality and the audio format to download.
+    ///
+    /// # Returns
+    /// - `Ok(usize)`: The count of downloaded items.
+    /// - `Err(String)`: An error message if the download directory couldn't be created, or the
+    ///   playlist couldn't be read again.
+    pub fn download(&mut self, format: &StreamFormat) -> Result<usize, String> {
+        download::directory()?;
+        self.unlock();
+        let total = self.items.len();
+        let mut downloaded = 0;
+        let mut files = HashMap::new();
+        for (index, item) in self.items.iter().enumerate() {
+            // Local files don't need to be downloaded
+            if !is_url(&item.url) || files.contains_key(&item.url) {
+                continue;
+            }
+            if let Some(existing) = download::find(&item.url) {
+                if existing.format.matches(format) {
+                    files.insert(item.url.clone(), existing);
+                    continue;
+                }
+            }
+            info(format!(
+                "Downloading {}/{}: {}",
+                index + 1,
+                total,
+                item.name()
+            ));
+            match download::download(&item.url, format) {
+                Ok(download) => {
+                    files.insert(item.url.clone(), download);
+                    downloaded += 1;
+                }
+                Err(e) => {
+                    warning(format!("Unable to download '{}'. Skipping.", item.name()));
+                    error(&e);
+                }
+            }
+        }
+
+        // Other playit processes may have changed the playlist while downloading
+        self.reread()?;
+        for item in &mut self.items {
+            if let Some(file) = files.get(&item.url) {
+                item.local_path = Some(file.path.clone());
+                item.local_format = Some(file.format.label());
+            }
+        }
+        Ok(downloaded)
+    }
+
+    /// Imports the new videos of every source of the playlist.
+    ///
+    /// # Returns
+    /// - `Ok(usize)`: The count of added items.
+    /// - `Err(String)`: An error message if the playlist has no sources or a source couldn't be
+    ///   fetched.
+    pub fn sync(&mut self) -> Result<usize, String> {
+        if self.sources.is_empty() {
+            return Err(
+                "The playlist has no sources. Import one with `playlist import-url`.".to_string(),
+            );
+        }
+        // The sources are fetched without holding the lock of the playlist
+        self.unlock();
+        let mut fetched = Vec::new();
+        for source in &self.sources {
+            fetched.push((source.clone(), youtube::playlist_entries(source)?));
+        }
+        self.reread()?;
+        Ok(fetched
+            .into_iter()
+            .map(|(source, entries)| self.import_entries(&source, entries))
+            .sum())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns an empty temporary playlist directory, unique to the test.
+    fn temp_directory(name: &str) -> String {
+        let directory =
+            std::env::temp_dir().join(format!("playit-playlist-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory.display().to_string()
+    }
+
+    fn json(path: &str) -> serde_json::Value {
+        serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn migrates_legacy_playlists() {
+        let directory = temp_directory("legacy");
+        let path = format!("{}/old.pl", directory);
+        fs::write(&path, r#"["q1","https://a/b"]"#).unwrap();
+
+        let playlist = Playlist::load("old", Some(&directory)).unwrap();
+        let items: Vec<_> = playlist
+            .items
+            .iter()
+            .map(|item| (item.query.as_str(), item.url.as_str(), item.title.is_none()))
+            .collect();
+        assert_eq!(
+            items,
+            [("q1", "q1", true), ("https://a/b", "https://a/b", true)]
+        );
+        // Reading doesn't change the file
+        assert_eq!(json(&path), serde_json::json!(["q1", "https://a/b"]));
+
+        playlist.write().unwrap();
+        assert_eq!(
+            json(&path),
+            serde_json::json!({
+                "version": 2,
+                "items": [
+                    {"query": "q1", "url": "q1"},
+                    {"query": "https://a/b", "url": "https://a/b"},
+                ],
+            })
+        );
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn round_trips_items() {
+        let directory = temp_directory("versioned");
+        let content = serde_json::json!({
+            "version": 2,
+            "items": [
+                {
+                    "query": "daft punk one more time",
+                    "url": "https://www.youtube.com/watch?v=FGBhQbmPwH8",
+                    "title": "Daft Punk - One More Time",
+                    "channel": "Daft Punk",
+                    "duration": 320.5,
+                    "added_at": 1740000000u64,
+                    "tags": ["dance", "french"],
+                    "local_path": "/music/one more time.opus",
+                    "local_format": "low, opus",
+                },
+                {"query": "/music/a.mp3", "url": "/music/a.mp3"},
+            ],
+            "sources": ["https://www.youtube.com/@daftpunk"],
+        });
+        let path = format!("{}/new.pl", directory);
+        fs::write(&path, content.to_string()).unwrap();
+
+        let playlist = Playlist::load("new", Some(&directory)).unwrap();
+        let item = &playlist.items[0];
+        assert_eq!(item.tags, ["dance", "french"]);
+        assert_eq!(item.local_format.as_deref(), Some("low, opus"));
+        assert_eq!(playlist.sources, ["https://www.youtube.com/@daftpunk"]);
+        playlist.write().unwrap();
+        assert_eq!(json(&path), content);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn renames_and_deletes_files() {
+        let directory = temp_directory("rename");
+        let files = || {
+            let mut files: Vec<String> = fs::read_dir(&directory)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+                .collect();
+            files.sort();
+            files
+        };
+        fs::write(format!("{}/a.pl", directory), "[]").unwrap();
+        fs::write(format!("{}/b.pl", directory), "[]").unwrap();
+        let playlist = Playlist::load("a", Some(&directory)).unwrap();
+        let mut playlist = playlist.write().unwrap();
+        assert!(playlist.rename("b", Some(&directory)).is_err());
+        assert!(playlist.rename("a", Some(&directory)).is_err());
+        assert!(playlist.copy("b", Some(&directory)).is_err());
+        playlist.rename("c", Some(&directory)).unwrap();
+        assert_eq!(
+            files(),
+            ["b.pl", "b.pl.lock", "c.pl", "c.pl.bak", "c.pl.lock"]
+        );
+        playlist.delete().unwrap();
+        assert_eq!(files(), ["b.pl", "b.pl.lock"]);
+        fs::remove_dir_all(&directory).unwrap();
+    }
 }
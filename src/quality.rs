@@ -0,0 +1,403 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// The quality of the streams chosen by `yt-dlp`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "QualityValue", into = "String")]
+pub enum Quality {
+    /// The best available streams.
+    Best,
+    /// Audio up to 160 kbps and video up to 720p.
+    Good,
+    /// Audio up to 64 kbps and video up to 360p, for saving bandwidth.
+    Low,
+    /// Audio up to the given bitrate in kbps.
+    Kbps(u32),
+}
+
+/// The ways of writing a quality in the configuration file (e.g., `"low"` or `96`).
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum QualityValue {
+    Name(String),
+    Kbps(u32),
+}
+
+impl Quality {
+    /// Returns the highest audio bitrate in kbps, if it is limited.
+    fn bitrate(self) -> Option<u32> {
+        match self {
+            Quality::Best => None,
+            Quality::Good => Some(160),
+            Quality::Low => Some(64),
+            Quality::Kbps(kbps) => Some(kbps),
+        }
+    }
+
+    /// Returns the highest video height, if it is limited.
+    fn height(self) -> Option<u32> {
+        match self {
+            Quality::Good => Some(720),
+            Quality::Low => Some(360),
+            Quality::Best | Quality::Kbps(_) => None,
+        }
+    }
+}
+
+impl FromStr for Quality {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "best" => Ok(Quality::Best),
+            "good" => Ok(Quality::Good),
+            "low" => Ok(Quality::Low),
+            other => match other.trim_end_matches("kbps").trim().parse::<u32>() {
+                Ok(kbps) if kbps > 0 => Ok(Quality::Kbps(kbps)),
+                _ => Err(format!(
+                    "Invalid quality '{}', expected best, good, low or a bitrate in kbps.",
+                    value
+                )),
+            },
+        }
+    }
+}
+
+impl TryFrom<QualityValue> for Quality {
+    type Error = String;
+
+    fn try_from(value: QualityValue) -> Result<Self, Self::Error> {
+        match value {
+            QualityValue::Name(name) => name.parse(),
+            QualityValue::Kbps(kbps) => Quality::from_str(&kbps.to_string()),
+        }
+    }
+}
+
+impl From<Quality> for String {
+    fn from(quality: Quality) -> Self {
+        quality.to_string()
+    }
+}
+
+impl fmt::Display for Quality {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Quality::Best => f.write_str("best"),
+            Quality::Good => f.write_str("good"),
+            Quality::Low => f.write_str("low"),
+            Quality::Kbps(kbps) => write!(f, "{}", kbps),
+        }
+    }
+}
+
+/// The audio formats which can be preferred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioFormat {
+    /// Opus audio (usually in a WebM container).
+    Opus,
+    /// AAC audio in an M4A container.
+    M4a,
+}
+
+impl AudioFormat {
+    /// Returns the `yt-dlp` filter for the streams in this format.
+    fn filter(self) -> &'static str {
+        match self {
+            AudioFormat::Opus => "[acodec=opus]",
+            AudioFormat::M4a => "[ext=m4a]",
+        }
+    }
+}
+
+/// The quality and the audio format of the streams chosen by user.
+///
+/// # Fields
+/// - `quality`: The quality of the streams. The best streams are used if not set.
+/// - `audio_format`: The preferred audio format, if any.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamFormat {
+    pub quality: Option<Quality>,
+    pub audio_format: Option<AudioFormat>,
+}
+
+impl StreamFormat {
+    /// Returns the `yt-dlp` format selector for the audio stream.
+    ///
+    /// The selector falls back to the other streams when none of the streams matches the
+    /// preferences, so the media can always be played.
+    pub fn audio_selector(&self) -> String {
+        let codec = self.audio_format.map(AudioFormat::filter).unwrap_or("");
+        let bitrate = self.bitrate_filter();
+        let mut selectors = vec![format!("bestaudio{}{}", codec, bitrate)];
+        selectors.push(format!("bestaudio{}", bitrate));
+        if !bitrate.is_empty() {
+            // The lowest bitrate is the closest one when none is under the limit
+            selectors.push(format!("worstaudio{}", codec));
+            selectors.push(String::from("worstaudio"));
+        }
+        selectors.push(String::from("bestaudio"));
+        selectors.push(String::from("best"));
+        selectors.dedup();
+        selectors.join("/")
+    }
+
+    /// Returns the `yt-dlp` format selector for mpv (its `ytdl-format` option).
+    ///
+    /// # Parameters
+    /// - `video`: Whether the video is played.
+    ///
+    /// # Returns
+    /// - `Some(String)`: The format selector.
+    /// - `None`: If the video is played and there are no preferences, so the default of mpv is
+    ///   used.
+    pub fn selector(&self, video: bool) -> Option<String> {
+        if !video {
+            return Some(self.audio_selector());
+        }
+        if self.quality.is_none() && self.audio_format.is_none() {
+            return None;
+        }
+        let height = match self.quality.and_then(Quality::height) {
+            Some(height) => format!("[height<=?{}]", height),
+            None => String::new(),
+        };
+        let codec = self.audio_format.map(AudioFormat::filter).unwrap_or("");
+        let bitrate = self.bitrate_filter();
+        let mut selectors = vec![
+            format!("bestvideo{}+bestaudio{}{}", height, codec, bitrate),
+            format!("bestvideo{}+bestaudio", height),
+            format!("best{}", height),
+            String::from("best"),
+        ];
+        selectors.dedup();
+        Some(selectors.join("/"))
+    }
+
+    /// Returns a short description of the format (e.g., `low, opus`), as recorded for downloads.
+    pub fn label(&self) -> String {
+        let quality = match self.quality.unwrap_or(Quality::Best) {
+            Quality::Kbps(kbps) => format!("{} kbps", kbps),
+            quality => quality.to_string(),
+        };
+        match self.audio_format {
+            Some(AudioFormat::Opus) => format!("{}, opus", quality),
+            Some(AudioFormat::M4a) => format!("{}, m4a", quality),
+            None => quality,
+        }
+    }
+
+    /// Returns a short name of the format for file names (e.g., `low-opus` or `96`).
+    pub fn tag(&self) -> String {
+        let quality = self.quality.unwrap_or(Quality::Best).to_string();
+        match self.audio_format {
+            Some(AudioFormat::Opus) => format!("{}-opus", quality),
+            Some(AudioFormat::M4a) => format!("{}-m4a", quality),
+            None => quality,
+        }
+    }
+
+    /// Reads a format from its short name, as returned by `tag`.
+    ///
+    /// # Returns
+    /// - `Some(StreamFormat)`: The format, with its quality always set.
+    /// - `None`: If the name is not a valid format.
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        let (quality, audio_format) = match tag.rsplit_once('-') {
+            Some((quality, audio_format)) => (
+                quality,
+                Some(<AudioFormat as ValueEnum>::from_str(audio_format, false).ok()?),
+            ),
+            None => (tag, None),
+        };
+        Some(StreamFormat {
+            quality: Some(quality.parse().ok()?),
+            audio_format,
+        })
+    }
+
+    /// Returns whether the streams chosen with both formats are the same.
+    pub fn matches(&self, other: &StreamFormat) -> bool {
+        self.tag() == other.tag()
+    }
+
+    /// Returns the `yt-dlp` filter for the audio bitrate, empty if it is not limited.
+    fn bitrate_filter(&self) -> String {
+        match self.quality.and_then(Quality::bitrate) {
+            // Streams without a known bitrate are kept, as `yt-dlp` can't compare them
+            Some(kbps) => format!("[abr<=?{}]", kbps),
+            None => String::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(quality: Option<Quality>, audio_format: Option<AudioFormat>) -> StreamFormat {
+        StreamFormat {
+            quality,
+            audio_format,
+        }
+    }
+
+    #[test]
+    fn parses_qualities() {
+        assert_eq!("best".parse(), Ok(Quality::Best));
+        assert_eq!("Good".parse(), Ok(Quality::Good));
+        assert_eq!("low".parse(), Ok(Quality::Low));
+        assert_eq!("96".parse(), Ok(Quality::Kbps(96)));
+        assert_eq!("128kbps".parse(), Ok(Quality::Kbps(128)));
+        assert!("0".parse::<Quality>().is_err());
+        assert!("meh".parse::<Quality>().is_err());
+    }
+
+    #[test]
+    fn reads_qualities_from_config() {
+        #[derive(Deserialize)]
+        struct Value {
+            quality: Quality,
+        }
+        let read = |content: &str| toml::from_str::<Value>(content).map(|value| value.quality);
+        assert_eq!(read("quality = \"low\"").unwrap(), Quality::Low);
+        assert_eq!(read("quality = 96").unwrap(), Quality::Kbps(96));
+        assert!(read("quality = \"meh\"").is_err());
+    }
+
+    #[test]
+    fn audio_selectors() {
+        let cases = [
+            (None, None, "bestaudio/best"),
+            (Some(Quality::Best), None, "bestaudio/best"),
+            (
+                Some(Quality::Good),
+                None,
+                "bestaudio[abr<=?160]/worstaudio/bestaudio/best",
+            ),
+            (
+                Some(Quality::Low),
+                None,
+                "bestaudio[abr<=?64]/worstaudio/bestaudio/best",
+            ),
+            (
+                Some(Quality::Kbps(96)),
+                None,
+                "bestaudio[abr<=?96]/worstaudio/bestaudio/best",
+            ),
+            (
+                None,
+                Some(AudioFormat::Opus),
+                "bestaudio[acodec=opus]/bestaudio/best",
+            ),
+            (
+                Some(Quality::Best),
+                Some(AudioFormat::M4a),
+                "bestaudio[ext=m4a]/bestaudio/best",
+            ),
+            (
+                Some(Quality::Good),
+                Some(AudioFormat::M4a),
+                "bestaudio[ext=m4a][abr<=?160]/bestaudio[abr<=?160]/worstaudio[ext=m4a]/worstaudio/bestaudio/best",
+            ),
+            (
+                Some(Quality::Low),
+                Some(AudioFormat::Opus),
+                "bestaudio[acodec=opus][abr<=?64]/bestaudio[abr<=?64]/worstaudio[acodec=opus]/worstaudio/bestaudio/best",
+            ),
+            (
+                Some(Quality::Kbps(48)),
+                Some(AudioFormat::Opus),
+                "bestaudio[acodec=opus][abr<=?48]/bestaudio[abr<=?48]/worstaudio[acodec=opus]/worstaudio/bestaudio/best",
+            ),
+        ];
+        for (quality, audio_format, expected) in cases {
+            let format = format(quality, audio_format);
+            assert_eq!(format.audio_selector(), expected, "{:?}", format);
+            // Without video, mpv uses the same selector
+            assert_eq!(format.selector(false).as_deref(), Some(expected));
+        }
+    }
+
+    #[test]
+    fn video_selectors() {
+        assert_eq!(format(None, None).selector(true), None);
+        let cases = [
+            (
+                Some(Quality::Best),
+                None,
+                "bestvideo+bestaudio/best",
+            ),
+            (
+                Some(Quality::Good),
+                None,
+                "bestvideo[height<=?720]+bestaudio[abr<=?160]/bestvideo[height<=?720]+bestaudio/best[height<=?720]/best",
+            ),
+            (
+                Some(Quality::Low),
+                Some(AudioFormat::Opus),
+                "bestvideo[height<=?360]+bestaudio[acodec=opus][abr<=?64]/bestvideo[height<=?360]+bestaudio/best[height<=?360]/best",
+            ),
+            (
+                Some(Quality::Kbps(96)),
+                None,
+                "bestvideo+bestaudio[abr<=?96]/bestvideo+bestaudio/best",
+            ),
+            (
+                None,
+                Some(AudioFormat::M4a),
+                "bestvideo+bestaudio[ext=m4a]/bestvideo+bestaudio/best",
+            ),
+        ];
+        for (quality, audio_format, expected) in cases {
+            let format = format(quality, audio_format);
+            assert_eq!(
+                format.selector(true).as_deref(),
+                Some(expected),
+                "{:?}",
+                format
+            );
+        }
+    }
+
+    #[test]
+    fn tags() {
+        let cases = [
+            (format(None, None), "best"),
+            (format(Some(Quality::Good), None), "good"),
+            (
+                format(Some(Quality::Low), Some(AudioFormat::Opus)),
+                "low-opus",
+            ),
+            (
+                format(Some(Quality::Kbps(96)), Some(AudioFormat::M4a)),
+                "96-m4a",
+            ),
+        ];
+        for (format, tag) in cases {
+            assert_eq!(format.tag(), tag);
+            let read = StreamFormat::from_tag(tag).unwrap();
+            assert!(read.matches(&format), "{}", tag);
+        }
+        assert!(format(None, None).matches(&format(Some(Quality::Best), None)));
+        assert!(!format(None, None).matches(&format(Some(Quality::Low), None)));
+        assert_eq!(StreamFormat::from_tag("low-flac"), None);
+        assert_eq!(StreamFormat::from_tag("meh"), None);
+    }
+
+    #[test]
+    fn labels() {
+        assert_eq!(format(None, None).label(), "best");
+        assert_eq!(
+            format(Some(Quality::Low), Some(AudioFormat::Opus)).label(),
+            "low, opus"
+        );
+        assert_eq!(
+            format(Some(Quality::Kbps(96)), Some(AudioFormat::M4a)).label(),
+            "96 kbps, m4a"
+        );
+    }
+}
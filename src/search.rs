@@ -0,0 +1,313 @@
+use crate::log::{error, warning};
+use crate::utils::{audio_files, normalize_query};
+use crate::youtube::{self, SearchResult};
+use clap::ValueEnum;
+use dirs::audio_dir;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Sources which can be searched for media.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Source {
+    /// YouTube.
+    #[value(alias = "yt")]
+    #[serde(alias = "yt")]
+    Youtube,
+    /// YouTube Music, which prefers the official audio of songs.
+    #[value(alias = "ytm")]
+    #[serde(alias = "ytm")]
+    YoutubeMusic,
+    /// SoundCloud.
+    #[value(alias = "sc")]
+    #[serde(alias = "sc")]
+    Soundcloud,
+    /// The audio files of the local music directory.
+    Local,
+}
+
+/// A backend which finds media for search queries.
+pub trait SearchProvider {
+    /// Returns the short name of the provider (e.g., `yt`), used in cache keys and logs.
+    fn name(&self) -> &str;
+
+    /// Searches for a query and returns the top results.
+    ///
+    /// # Parameters
+    /// - `query`: The search query (e.g., song name or video title).
+    /// - `count`: The maximum count of results.
+    ///
+    /// # Returns
+    /// - `Ok(Vec<SearchResult>)`: The results, best first. Never empty.
+    /// - `Err(String)`: An error message if the search fails or finds nothing.
+    fn search_many(&self, query: &str, count: usize) -> Result<Vec<SearchResult>, String>;
+
+    /// Searches for a query and returns the first result.
+    ///
+    /// # Parameters
+    /// - `query`: The search query (e.g., song name or video title).
+    ///
+    /// # Returns
+    /// - `Ok(SearchResult)`: The first result.
+    /// - `Err(String)`: An error message if the search fails or finds nothing.
+    fn search(&self, query: &str) -> Result<SearchResult, String> {
+        self.search_many(query, 1)?
+            .into_iter()
+            .next()
+            .ok_or(format!("No results found for '{}'", query))
+    }
+
+    /// Returns whether the provider works without the network.
+    fn is_local(&self) -> bool {
+        false
+    }
+}
+
+/// A site searched with `yt-dlp`.
+///
+/// # Fields
+/// - `name`: The short name of the site.
+/// - `prefix`: The search prefix of `yt-dlp` for the site (e.g., `ytsearch`).
+pub struct YtDlp {
+    name: &'static str,
+    prefix: &'static str,
+}
+
+impl YtDlp {
+    /// Creates a provider searching YouTube.
+    pub fn youtube() -> Self {
+        YtDlp {
+            name: "yt",
+            prefix: "ytsearch",
+        }
+    }
+
+    /// Creates a provider searching YouTube Music.
+    pub fn youtube_music() -> Self {
+        YtDlp {
+            name: "ytm",
+            prefix: "ytmsearch",
+        }
+    }
+
+    /// Creates a provider searching SoundCloud.
+    pub fn soundcloud() -> Self {
+        YtDlp {
+            name: "sc",
+            prefix: "scsearch",
+        }
+    }
+}
+
+impl SearchProvider for YtDlp {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn search_many(&self, query: &str, count: usize) -> Result<Vec<SearchResult>, String> {
+        youtube::search_results(query, self.prefix, count)
+    }
+
+    fn search(&self, query: &str) -> Result<SearchResult, String> {
+        // The full metadata of the first result is fetched, not only the listed fields
+        youtube::search(query, self.prefix)
+    }
+}
+
+/// A directory of audio files, searched by their paths.
+///
+/// # Fields
+/// - `directory`: The music directory. `None` if it couldn't be determined.
+pub struct LocalLibrary {
+    directory: Option<PathBuf>,
+}
+
+impl LocalLibrary {
+    /// Creates a provider searching the given directory, or the music directory of the user.
+    ///
+    /// # Parameters
+    /// - `directory`: The music directory given by user, if any.
+    pub fn new(directory: Option<&str>) -> Self {
+        LocalLibrary {
+            directory: directory.map(PathBuf::from).or_else(audio_dir),
+        }
+    }
+}
+
+impl SearchProvider for LocalLibrary {
+    fn name(&self) -> &str {
+        "local"
+    }
+
+    fn is_local(&self) -> bool {
+        true
+    }
+
+    fn search_many(&self, query: &str, count: usize) -> Result<Vec<SearchResult>, String> {
+        let directory = self.directory.as_ref().ok_or(
+            "Unable to find the music directory. Specify one with `--music-dir`.".to_string(),
+        )?;
+        if !directory.is_dir() {
+            return Err(format!(
+                "The music directory '{}' doesn't exist.",
+                directory.display()
+            ));
+        }
+
+        // Every word of the query should be in the path of the file, in any order
+        let words: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+        let results: Vec<SearchResult> = audio_files(directory)
+            .into_iter()
+            .filter(|file| {
+                let path = file
+                    .strip_prefix(directory)
+                    .unwrap_or(file)
+                    .to_string_lossy()
+                    .to_lowercase();
+                words.iter().all(|word| path.contains(word.as_str()))
+            })
+            .take(count)
+            .map(|file| SearchResult::from_path(&file.display().to_string()))
+            .collect();
+        if results.is_empty() {
+            return Err(format!(
+                "No files found for '{}' in '{}'",
+                query,
+                directory.display()
+            ));
+        }
+        Ok(results)
+    }
+}
+
+/// Searches the providers in order, falling back to the next one when a provider fails or finds
+/// nothing.
+///
+/// # Fields
+/// - `providers`: The providers to search, in order.
+/// - `names`: The names of the sources given by user, joined with commas.
+/// - `music_dir`: The music directory for the local source, if given by user.
+/// - `offline`: Whether the network shouldn't be used.
+pub struct Searcher {
+    providers: Vec<Box<dyn SearchProvider>>,
+    names: String,
+    music_dir: Option<String>,
+    offline: bool,
+}
+
+impl Searcher {
+    /// Creates a searcher for the given sources.
+    ///
+    /// # Parameters
+    /// - `sources`: The sources to search, in order. YouTube is searched if it is empty.
+    /// - `music_dir`: The music directory for the local source, if given by user.
+    /// - `offline`: Search only the sources which don't need the network.
+    pub fn new(sources: &[Source], music_dir: Option<&str>, offline: bool) -> Self {
+        let mut providers: Vec<Box<dyn SearchProvider>> = Vec::new();
+        for source in sources {
+            providers.push(match source {
+                Source::Youtube => Box::new(YtDlp::youtube()),
+                Source::YoutubeMusic => Box::new(YtDlp::youtube_music()),
+                Source::Soundcloud => Box::new(YtDlp::soundcloud()),
+                Source::Local => Box::new(LocalLibrary::new(music_dir)),
+            });
+        }
+        if providers.is_empty() {
+            providers.push(Box::new(YtDlp::youtube()));
+        }
+
+        // The names are kept for the cache, so the cached results are found in offline mode too
+        let names: Vec<&str> = providers.iter().map(|p| p.name()).collect();
+        let names = names.join(",");
+        if offline {
+            providers.retain(|provider| provider.is_local());
+        }
+        Searcher {
+            providers,
+            names,
+            music_dir: music_dir.map(str::to_string),
+            offline,
+        }
+    }
+
+    /// Creates a searcher for other sources, keeping the music directory and the offline mode.
+    ///
+    /// # Parameters
+    /// - `sources`: The sources to search, in order.
+    pub fn with_sources(&self, sources: &[Source]) -> Self {
+        Searcher::new(sources, self.music_dir.as_deref(), self.offline)
+    }
+
+    /// Returns whether the searcher is in offline mode.
+    pub fn is_offline(&self) -> bool {
+        self.offline
+    }
+
+    /// Returns the key of a query in the cache. The query is normalized, so trivially different
+    /// queries share a key. Queries searched only on YouTube are cached without a prefix, the
+    /// others are prefixed with the names of the providers (e.g., `sc:query`).
+    pub fn cache_key(&self, query: &str) -> String {
+        let query = normalize_query(query);
+        if self.names == "yt" {
+            query
+        } else {
+            format!("{}:{}", self.names, query)
+        }
+    }
+
+    /// Searches for a query and returns the first result of the first provider finding it.
+    ///
+    /// # Parameters
+    /// - `query`: The search query (e.g., song name or video title).
+    ///
+    /// # Returns
+    /// - `Ok(SearchResult)`: The first result.
+    /// - `Err(String)`: The error of the last provider if none of them found anything.
+    pub fn search(&self, query: &str) -> Result<SearchResult, String> {
+        self.fallback(|provider| provider.search(query))
+    }
+
+    /// Searches for a query and returns the top results of the first provider finding it.
+    ///
+    /// # Parameters
+    /// - `query`: The search query (e.g., song name or video title).
+    /// - `count`: The maximum count of results.
+    ///
+    /// # Returns
+    /// - `Ok(Vec<SearchResult>)`: The results, best first.
+    /// - `Err(String)`: The error of the last provider if none of them found anything.
+    pub fn search_many(&self, query: &str, count: usize) -> Result<Vec<SearchResult>, String> {
+        self.fallback(|provider| provider.search_many(query, count))
+    }
+
+    /// Runs a search on the providers in order until one of them succeeds.
+    fn fallback<T>(
+        &self,
+        search: impl Fn(&dyn SearchProvider) -> Result<T, String>,
+    ) -> Result<T, String> {
+        if self.providers.is_empty() {
+            return Err(
+                "Searching needs the network in offline mode. Use `--source local` for searching \
+                 the music directory."
+                    .to_string(),
+            );
+        }
+        let mut last_error = String::new();
+        for (index, provider) in self.providers.iter().enumerate() {
+            match search(provider.as_ref()) {
+                Ok(result) => return Ok(result),
+                Err(e) => {
+                    if index + 1 < self.providers.len() {
+                        warning(format!(
+                            "Search on '{}' failed, trying the next source.",
+                            provider.name()
+                        ));
+                        error(&e);
+                    }
+                    last_error = e;
+                }
+            }
+        }
+        Err(last_error)
+    }
+}
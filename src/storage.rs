@@ -0,0 +1,244 @@
+use crate::log::{error, info, warning};
+use serde::de::DeserializeOwned;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::Write;
+
+/// An advisory lock on a data file, which is released when it is dropped.
+///
+/// The lock is taken on a `.lock` file next to the data file, since the data file itself is
+/// replaced on every write.
+pub struct FileLock {
+    _file: File,
+}
+
+/// Locks a data file, waiting for the other playit processes holding its lock.
+///
+/// # Parameters
+/// - `path`: The path of the data file.
+///
+/// # Returns
+/// - `Ok(FileLock)`: The lock, held until it is dropped.
+/// - `Err(String)`: An error message if the lock file couldn't be created or locked.
+pub fn lock(path: &str) -> Result<FileLock, String> {
+    let lock_path = lock_path(path);
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .map_err(|e| format!("Unable to open lock file {}: {}", lock_path, e))?;
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => {
+            info(format!(
+                "Waiting for another playit process using: {}",
+                path
+            ));
+            file.lock()
+                .map_err(|e| format!("Unable to lock {}: {}", path, e))?;
+        }
+        Err(TryLockError::Error(e)) => return Err(format!("Unable to lock {}: {}", path, e)),
+    }
+    Ok(FileLock { _file: file })
+}
+
+/// Reads and parses a JSON data file, recovering the last good copy if it can't be parsed.
+///
+/// When the file is corrupted, it is moved aside as `.corrupt` and its backup (`.bak`, kept by
+/// `write`) is restored in its place.
+///
+/// # Parameters
+/// - `path`: The path of the data file.
+///
+/// # Returns
+/// - `Ok(T)`: The parsed content of the file or of its backup.
+/// - `Err(String)`: An error message if the file couldn't be read, or neither the file nor its
+///   backup could be parsed.
+pub fn read<T: DeserializeOwned>(path: &str) -> Result<T, String> {
+    let content =
+        fs::read_to_string(path).map_err(|e| format!("Unable to read {}: {}", path, e))?;
+    let parse_error = match serde_json::from_str::<T>(&content) {
+        Ok(data) => return Ok(data),
+        Err(e) => e,
+    };
+
+    let backup = backup_path(path);
+    let recovered = fs::read_to_string(&backup)
+        .ok()
+        .and_then(|backup| Some((serde_json::from_str::<T>(&backup).ok()?, backup)));
+    let Some((data, backup_content)) = recovered else {
+        return Err(format!(
+            "Failed to parse {} ({}). It might be corrupted.",
+            path, parse_error
+        ));
+    };
+
+    warning(format!(
+        "{} is corrupted ({}), restoring the last good copy.",
+        path, parse_error
+    ));
+    let corrupt = format!("{}.corrupt", path);
+    if let Err(e) = fs::rename(path, &corrupt).and_then(|_| replace(path, &backup_content)) {
+        warning("Unable to restore the last good copy, it is used without saving.");
+        error(e);
+    } else {
+        info(format!("The corrupted file is kept at: {}", corrupt));
+    }
+    Ok(data)
+}
+
+/// Writes a data file atomically, keeping the previous content as its backup (`.bak`).
+///
+/// The content is written to a temporary file which then replaces the data file, so other
+/// processes never see a partially written file.
+///
+/// # Parameters
+/// - `path`: The path of the data file.
+/// - `content`: The new content of the file.
+///
+/// # Returns
+/// - `Ok(())` if the file was written.
+/// - `Err(String)` if the file couldn't be written.
+pub fn write(path: &str, content: &str) -> Result<(), String> {
+    if fs::exists(path).unwrap_or(false) {
+        let backup = backup_path(path);
+        // A hard link keeps the previous content without copying it, as the rename below
+        // replaces the data file with a new one
+        let _ = fs::remove_file(&backup);
+        if fs::hard_link(path, &backup).is_err() {
+            if let Err(e) = fs::copy(path, &backup) {
+                warning(format!("Unable to back up {}: {}", path, e));
+            }
+        }
+    }
+    replace(path, content).map_err(|e| format!("Unable to write {}: {}", path, e))
+}
+
+/// Removes the backup of a data file, if there is.
+///
+/// # Parameters
+/// - `path`: The path of the data file.
+pub fn remove_backup(path: &str) {
+    let _ = fs::remove_file(backup_path(path));
+}
+
+/// Removes the lock file of a data file which was removed or moved away, so lock files don't
+/// pile up. It should be called while holding the lock.
+///
+/// # Parameters
+/// - `path`: The path of the data file.
+pub fn remove_lock(path: &str) {
+    let _ = fs::remove_file(lock_path(path));
+}
+
+/// Returns the path of the lock file of a data file.
+fn lock_path(path: &str) -> String {
+    format!("{}.lock", path)
+}
+
+/// Returns the path of the backup of a data file.
+pub fn backup_path(path: &str) -> String {
+    format!("{}.bak", path)
+}
+
+/// Replaces the content of a file with a temporary file, without keeping a backup.
+fn replace(path: &str, content: &str) -> std::io::Result<()> {
+    let temp = format!("{}.tmp{}", path, std::process::id());
+    let result = File::create(&temp)
+        .and_then(|mut file| {
+            file.write_all(content.as_bytes())?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&temp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the path of a data file in an empty temporary directory, unique to the test.
+    fn data_file(name: &str) -> (std::path::PathBuf, String) {
+        let directory =
+            std::env::temp_dir().join(format!("playit-storage-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("data.json").display().to_string();
+        (directory, path)
+    }
+
+    /// Returns the names of the files in a directory, sorted.
+    fn files(directory: &std::path::Path) -> Vec<String> {
+        let mut files: Vec<String> = fs::read_dir(directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn writes_with_backups() {
+        let (directory, path) = data_file("write");
+        write(&path, "[1]").unwrap();
+        assert_eq!(files(&directory), ["data.json"]);
+        write(&path, "[1,2]").unwrap();
+        // No temporary file is left behind
+        assert_eq!(files(&directory), ["data.json", "data.json.bak"]);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[1,2]");
+        assert_eq!(fs::read_to_string(backup_path(&path)).unwrap(), "[1]");
+        assert_eq!(read::<Vec<u32>>(&path).unwrap(), [1, 2]);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn recovers_truncated_files() {
+        let (directory, path) = data_file("recover");
+        write(&path, "[1]").unwrap();
+        write(&path, "[1,2,3]").unwrap();
+        // An interrupted write of another program
+        fs::write(&path, "[1,2").unwrap();
+
+        assert_eq!(read::<Vec<u32>>(&path).unwrap(), [1]);
+        assert_eq!(
+            files(&directory),
+            ["data.json", "data.json.bak", "data.json.corrupt"]
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[1]");
+        assert_eq!(
+            fs::read_to_string(format!("{}.corrupt", path)).unwrap(),
+            "[1,2"
+        );
+        assert_eq!(read::<Vec<u32>>(&path).unwrap(), [1]);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn fails_without_good_copies() {
+        let (directory, path) = data_file("corrupt");
+        fs::write(&path, "[1,2").unwrap();
+        assert!(read::<Vec<u32>>(&path).is_err());
+        fs::write(backup_path(&path), "{").unwrap();
+        assert!(read::<Vec<u32>>(&path).is_err());
+        // The files are left as they are
+        assert_eq!(files(&directory), ["data.json", "data.json.bak"]);
+        assert!(read::<Vec<u32>>(&format!("{}.missing", path)).is_err());
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn removes_lock_files() {
+        let (directory, path) = data_file("lock");
+        let lock = lock(&path).unwrap();
+        write(&path, "[]").unwrap();
+        assert_eq!(files(&directory), ["data.json", "data.json.lock"]);
+        fs::remove_file(&path).unwrap();
+        remove_lock(&path);
+        drop(lock);
+        assert!(files(&directory).is_empty());
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -1,10 +1,328 @@
-/// Checks if a given text is a valid URL (starts with "http://" or "https://")
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use unicode_normalization::UnicodeNormalization;
+
+/// Extensions of the files played from directories and found by the local library search.
+pub const AUDIO_EXTENSIONS: &[&str] = &[
+    "aac", "aiff", "alac", "ape", "flac", "m4a", "mka", "mp3", "oga", "ogg", "opus", "wav", "webm",
+    "wma",
+];
+
+/// Checks if a given text is a valid URL (starts with a scheme like "https://" or "rtmp://")
 ///
 /// # Parameters
 /// - `text`: The string to check
 ///
 /// # Returns
-/// - `true` if the text starts with "http://" or "https://", otherwise `false`
+/// - `true` if the text starts with a URL scheme followed by "://", otherwise `false`
 pub fn is_url(text: &str) -> bool {
-    text.starts_with("http://") || text.starts_with("https://")
+    let Some((scheme, _)) = text.split_once("://") else {
+        return false;
+    };
+    let mut characters = scheme.chars();
+    characters.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && characters.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+}
+
+/// Normalizes a search query, so queries which differ only in case, spacing or punctuation are
+/// the same (e.g., `Daft Punk - One More Time` and `daft punk one more time`)
+///
+/// The query is normalized with Unicode NFKC and lowercased, apostrophes are removed, and other
+/// punctuation and whitespace are collapsed into single spaces.
+///
+/// # Parameters
+/// - `query`: The search query to normalize
+///
+/// # Returns
+/// - The normalized query, or the trimmed and lowercased query if it is only punctuation
+pub fn normalize_query(query: &str) -> String {
+    let folded: String = query.nfkc().collect::<String>().to_lowercase();
+    let cleaned: String = folded
+        .chars()
+        .filter(|c| !matches!(c, '\'' | '\u{2019}'))
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect();
+    let normalized = cleaned.split_whitespace().collect::<Vec<&str>>().join(" ");
+    if normalized.is_empty() {
+        folded.trim().to_string()
+    } else {
+        normalized
+    }
+}
+
+/// Formats an amount of seconds as `mm:ss` (or `h:mm:ss` for longer durations)
+///
+/// # Parameters
+/// - `seconds`: The amount of seconds to format
+///
+/// # Returns
+/// - A string like `03:25` or `1:02:09`
+pub fn format_duration(seconds: f64) -> String {
+    let total = seconds.max(0.0) as u64;
+    let (hours, minutes, seconds) = (total / 3600, (total % 3600) / 60, total % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{:02}:{:02}", minutes, seconds)
+    }
+}
+
+/// Formats the time passed since a UNIX timestamp in a short form
+///
+/// # Parameters
+/// - `timestamp`: The UNIX timestamp of the past moment
+///
+/// # Returns
+/// - A string like `just now`, `5m ago`, `3h ago` or `12d ago`
+pub fn format_age(timestamp: u64) -> String {
+    let age = now().saturating_sub(timestamp);
+    match age {
+        0..60 => "just now".to_string(),
+        60..3600 => format!("{}m ago", age / 60),
+        3600..86400 => format!("{}h ago", age / 3600),
+        _ => format!("{}d ago", age / 86400),
+    }
+}
+
+/// Formats an amount of bytes in a short form
+///
+/// # Parameters
+/// - `bytes`: The amount of bytes to format
+///
+/// # Returns
+/// - A string like `512 B`, `4.2 KiB` or `1.3 MiB`
+pub fn format_size(bytes: u64) -> String {
+    match bytes {
+        0..1024 => format!("{} B", bytes),
+        1024..1_048_576 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MiB", bytes as f64 / 1_048_576.0),
+    }
+}
+
+/// Returns the current time as a UNIX timestamp
+///
+/// # Returns
+/// - The amount of seconds since 1970-01-01 00:00:00 UTC
+pub fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// Returns the local files a text refers to, if it is an existing path or a `file://` URI.
+///
+/// Files are returned as they are, and directories are expanded to their audio files (including
+/// the ones in subdirectories), sorted by path. The paths are made absolute, so they can be
+/// played from any directory.
+///
+/// # Parameters
+/// - `text`: The path or URI to check
+///
+/// # Returns
+/// - `Some(Vec<String>)` if the text is a local path. Empty for directories without audio files.
+/// - `None` if the text is not an existing local path
+pub fn local_files(text: &str) -> Option<Vec<String>> {
+    let path = file_uri_path(text).unwrap_or_else(|| text.to_string());
+    let path = fs::canonicalize(path).ok()?;
+    if path.is_dir() {
+        Some(
+            audio_files(&path)
+                .into_iter()
+                .map(|file| file.display().to_string())
+                .collect(),
+        )
+    } else {
+        Some(vec![path.display().to_string()])
+    }
+}
+
+/// Returns the audio files of a directory and its subdirectories, sorted by path
+///
+/// # Parameters
+/// - `directory`: The directory to search
+///
+/// # Returns
+/// - The paths of the files with an extension in `AUDIO_EXTENSIONS`
+pub fn audio_files(directory: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    collect_audio_files(directory, &mut HashSet::new(), &mut files);
+    files.sort();
+    files
+}
+
+/// Collects the audio files of a directory, following symbolic links but skipping the
+/// directories already visited (e.g., a link to a parent directory).
+fn collect_audio_files(directory: &Path, visited: &mut HashSet<PathBuf>, files: &mut Vec<PathBuf>) {
+    let Ok(canonical) = fs::canonicalize(directory) else {
+        return;
+    };
+    if !visited.insert(canonical) {
+        return;
+    }
+    let Ok(entries) = fs::read_dir(directory) else {
+        return;
+    };
+    for path in entries.filter_map(|entry| Some(entry.ok()?.path())) {
+        if path.is_dir() {
+            collect_audio_files(&path, visited, files);
+        } else if path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| AUDIO_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+        {
+            files.push(path);
+        }
+    }
+}
+
+/// Returns the path of a `file://` URI, with its percent-encoded characters (e.g., `%20`)
+/// decoded.
+///
+/// # Parameters
+/// - `text`: The text to check
+///
+/// # Returns
+/// - `Some(String)` with the path if the text is a `file://` URI
+/// - `None` if the text is not a `file://` URI
+pub fn file_uri_path(text: &str) -> Option<String> {
+    let uri = text.strip_prefix("file://")?;
+    // `file://localhost/path` is the same as `file:///path`
+    Some(decode_uri(uri.strip_prefix("localhost").unwrap_or(uri)))
+}
+
+/// Returns the `file://` URI of an absolute path, with the characters which are not allowed in
+/// URIs percent-encoded (e.g., spaces as `%20`). Relative paths are only encoded.
+///
+/// # Parameters
+/// - `path`: The path of the file
+///
+/// # Returns
+/// - The URI of the file
+pub fn file_uri(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    if Path::new(path).is_absolute() {
+        format!("file://{}", encoded)
+    } else {
+        encoded
+    }
+}
+
+/// Decodes the percent-encoded characters (e.g., `%20`) of a URI
+pub fn decode_uri(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let hex = bytes
+            .get(index + 1..index + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[index], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns an empty temporary directory, unique to the test.
+    fn directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("playit-utils-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        fs::canonicalize(directory).unwrap()
+    }
+
+    #[test]
+    fn decodes_uris() {
+        assert_eq!(decode_uri("/music/a%20b.mp3"), "/music/a b.mp3");
+        assert_eq!(decode_uri("/m%C3%BCsik/%C5%9Fark%C4%B1"), "/müsik/şarkı");
+        // Invalid escapes are kept as they are
+        assert_eq!(decode_uri("/100%/a%zz%2"), "/100%/a%zz%2");
+        assert_eq!(file_uri_path("file:///a%20b"), Some(String::from("/a b")));
+        assert_eq!(
+            file_uri_path("file://localhost/a"),
+            Some(String::from("/a"))
+        );
+        assert_eq!(file_uri_path("https://a/b"), None);
+        assert_eq!(file_uri_path("/a"), None);
+        assert_eq!(
+            file_uri("/m üsik/a&b#1.mp3"),
+            "file:///m%20%C3%BCsik/a%26b%231.mp3"
+        );
+        assert_eq!(file_uri("a b.mp3"), "a%20b.mp3");
+        assert_eq!(
+            file_uri_path(&file_uri("/m üsik/a&b#1.mp3")),
+            Some(String::from("/m üsik/a&b#1.mp3"))
+        );
+    }
+
+    #[test]
+    fn finds_audio_files() {
+        let directory = directory("audio");
+        fs::create_dir_all(directory.join("b/c")).unwrap();
+        for file in ["b/c/2.FLAC", "b/1.mp3", "cover.jpg", "0.opus", "notes"] {
+            fs::write(directory.join(file), "").unwrap();
+        }
+        assert_eq!(
+            audio_files(&directory),
+            ["0.opus", "b/1.mp3", "b/c/2.FLAC"].map(|file| directory.join(file))
+        );
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn skips_symlink_loops() {
+        let directory = directory("loop");
+        fs::create_dir_all(directory.join("a")).unwrap();
+        fs::write(directory.join("a/1.mp3"), "").unwrap();
+        std::os::unix::fs::symlink("..", directory.join("a/parent")).unwrap();
+        std::os::unix::fs::symlink(".", directory.join("a/itself")).unwrap();
+        let files = audio_files(&directory);
+        assert_eq!(files.len(), 1, "{:?}", files);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn finds_local_files() {
+        let directory = directory("local");
+        let file = directory.join("a b.mp3");
+        fs::write(&file, "").unwrap();
+        let file = file.display().to_string();
+        assert_eq!(local_files(&file), Some(vec![file.clone()]));
+        let uri = format!("file://{}", file.replace(' ', "%20"));
+        assert_eq!(local_files(&uri), Some(vec![file.clone()]));
+        assert_eq!(
+            local_files(&directory.display().to_string()),
+            Some(vec![file])
+        );
+        assert_eq!(
+            local_files(&directory.join("missing").display().to_string()),
+            None
+        );
+        assert_eq!(local_files("never gonna give you up"), None);
+        fs::remove_dir_all(directory).unwrap();
+    }
 }
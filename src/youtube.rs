@@ -1,32 +1,299 @@
+use serde::{Deserialize, Serialize};
 use std::process::Command;
+use std::sync::OnceLock;
 
-/// Searches for a media URL using `yt-dlp` based on a given query.
+/// The `yt-dlp` program set by user, if any.
+static PROGRAM: OnceLock<String> = OnceLock::new();
+
+/// Sets the path of the `yt-dlp` program used for the rest of the run.
+///
+/// # Parameters
+/// - `path`: The path or name of the program.
+pub fn set_program(path: &str) {
+    let _ = PROGRAM.set(path.to_string());
+}
+
+/// Returns the path of the `yt-dlp` program, `yt-dlp` from `$PATH` by default.
+pub fn program() -> &'static str {
+    PROGRAM.get().map(String::as_str).unwrap_or("yt-dlp")
+}
+
+/// Represents a media found by `yt-dlp`, with its metadata.
+///
+/// # Fields
+/// - `id`: The ID of the media (e.g., the YouTube video ID).
+/// - `url`: The URL of the media page.
+/// - `title`: The title of the media.
+/// - `uploader`: The name of the channel or the artist who uploaded the media.
+/// - `duration`: The duration of the media in seconds.
+/// - `thumbnail`: The URL of the thumbnail image.
+/// - `is_live`: Whether the media is a live stream.
+/// - `views`: The view count of the media.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SearchResult {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uploader: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub is_live: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub views: Option<u64>,
+}
+
+impl SearchResult {
+    /// Creates a result with only the URL known (e.g., for URLs given by user).
+    pub fn from_url(url: &str) -> Self {
+        SearchResult {
+            url: url.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Creates a result for a local file, titled with the file name without extension.
+    pub fn from_path(path: &str) -> Self {
+        SearchResult {
+            url: path.to_string(),
+            title: std::path::Path::new(path)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// Returns the title of the media, or the URL if the title is unknown.
+    pub fn name(&self) -> &str {
+        self.title.as_deref().unwrap_or(&self.url)
+    }
+}
+
+/// The metadata JSON of a media printed by `yt-dlp --dump-json`.
+#[derive(Deserialize)]
+struct MediaInfo {
+    id: Option<String>,
+    // `url` is the URL of the stream, the page is `webpage_url`
+    webpage_url: Option<String>,
+    title: Option<String>,
+    uploader: Option<String>,
+    channel: Option<String>,
+    duration: Option<f64>,
+    thumbnail: Option<String>,
+    is_live: Option<bool>,
+    view_count: Option<u64>,
+}
+
+impl MediaInfo {
+    /// Converts the metadata into a result, using `url` when the page URL is missing.
+    fn into_result(self, url: &str) -> SearchResult {
+        SearchResult {
+            id: self.id,
+            url: self.webpage_url.unwrap_or_else(|| url.to_string()),
+            title: self.title,
+            uploader: self.uploader.or(self.channel),
+            duration: self.duration,
+            thumbnail: self.thumbnail,
+            is_live: self.is_live.unwrap_or(false),
+            views: self.view_count,
+        }
+    }
+}
+
+/// An entry of the flat playlist JSON printed by `yt-dlp`.
+#[derive(Deserialize)]
+struct FlatEntry {
+    id: Option<String>,
+    url: Option<String>,
+    title: Option<String>,
+    channel: Option<String>,
+    uploader: Option<String>,
+    duration: Option<f64>,
+    view_count: Option<u64>,
+    live_status: Option<String>,
+    #[serde(default)]
+    thumbnails: Vec<Thumbnail>,
+    ie_key: Option<String>,
+    #[serde(default)]
+    entries: Vec<FlatEntry>,
+}
+
+/// A thumbnail of a flat playlist entry.
+#[derive(Deserialize)]
+struct Thumbnail {
+    url: String,
+}
+
+impl FlatEntry {
+    /// Converts the entry into a result, if it has a URL.
+    fn into_result(self) -> Option<SearchResult> {
+        Some(SearchResult {
+            id: self.id,
+            url: self.url?,
+            title: self.title,
+            uploader: self.channel.or(self.uploader),
+            duration: self.duration,
+            // The last thumbnail is the largest one
+            thumbnail: self.thumbnails.into_iter().last().map(|t| t.url),
+            is_live: self.live_status.as_deref() == Some("is_live"),
+            views: self.view_count,
+        })
+    }
+}
+
+/// Searches for a media using `yt-dlp` based on a given query.
 ///
 /// # Parameters
 /// - `query`: The search query (e.g., song name or video title).
+/// - `prefix`: The search prefix of `yt-dlp` for the site (e.g., `ytsearch`, `scsearch`).
 ///
 /// # Returns
-/// - `Ok(String)`: The media URL if found successfully.
-/// - `Err(String)`: An error message if the search fails.
-pub fn search(query: &str) -> Result<String, String> {
+/// - `Ok(SearchResult)`: The first result of the search.
+/// - `Err(String)`: An error message if the search fails or finds nothing.
+pub fn search(query: &str, prefix: &str) -> Result<SearchResult, String> {
     // Run the `yt-dlp` command with the provided search query
-    let output = Command::new("yt-dlp")
+    let output = Command::new(program())
         .arg("--no-playlist") // Avoid playlist downloads
         .arg("--quiet") // Suppress unnecessary output
         .arg("--simulate") // Simulate the download process (no actual download)
-        .arg("--print") // Print the output (in this case, the URL)
-        .arg("\"%(webpage_url)s\"") // Format to print the webpage URL
-        .arg(format!("ytsearch:{}", query)) // Construct the search query for yt-dlp
+        .arg("--dump-json") // Print the metadata of the result as JSON
+        .arg(format!("{}:{}", prefix, query)) // Construct the search query for yt-dlp
         .output(); // Capture the output of the command
 
     // Handle potential errors in running the command
     let output = output
         .map_err(|_| "Unable to create process for searching URL with 'yt-dlp'".to_string())?;
 
-    // Convert the command's stdout (output) from bytes to a String
-    let stdout = String::from_utf8(output.stdout)
-        .map_err(|_| "Unable to convert u8 bytes to string".to_string())?;
+    if output.stdout.iter().all(u8::is_ascii_whitespace) {
+        return Err(format!("No results found for '{}'", query));
+    }
+    let info: MediaInfo = serde_json::from_slice(&output.stdout)
+        .map_err(|_| format!("Unable to parse the search result of '{}'", query))?;
+    match info.webpage_url {
+        Some(_) => Ok(info.into_result("")),
+        None => Err(format!("No results found for '{}'", query)),
+    }
+}
+
+/// Checks if an error of `yt-dlp` means that the media is gone or blocked, rather than a
+/// network problem.
+///
+/// # Parameters
+/// - `message`: The error message, including the output of `yt-dlp`.
+///
+/// # Returns
+/// `true` if the media can't be played anymore (e.g., deleted, private or region-blocked).
+pub fn is_unavailable(message: &str) -> bool {
+    let message = message.to_lowercase();
+    [
+        "video unavailable",
+        "is not available",
+        "has been removed",
+        "private video",
+        "account associated with this video has been terminated",
+        "not made this video available in your country",
+    ]
+    .iter()
+    .any(|pattern| message.contains(pattern))
+}
+
+/// Fetches the metadata of a media URL using `yt-dlp`.
+///
+/// # Parameters
+/// - `url`: The URL of the media.
+///
+/// # Returns
+/// - `Ok(SearchResult)`: The media with its metadata.
+/// - `Err(String)`: An error message if `yt-dlp` failed.
+pub fn metadata(url: &str) -> Result<SearchResult, String> {
+    let output = Command::new(program())
+        .arg("--no-playlist") // Avoid playlist downloads
+        .arg("--quiet") // Suppress unnecessary output
+        .arg("--simulate") // Simulate the download process (no actual download)
+        .arg("--dump-json") // Print the metadata as JSON
+        .arg(url)
+        .output()
+        .map_err(|_| "Unable to create process for fetching metadata with 'yt-dlp'".to_string())?;
+
+    serde_json::from_slice::<MediaInfo>(&output.stdout)
+        .map(|info| info.into_result(url))
+        .map_err(|_| format!("Unable to parse the metadata of '{}'", url))
+}
+
+/// Searches for a query using `yt-dlp` and returns the top results with their metadata.
+///
+/// # Parameters
+/// - `query`: The search query (e.g., song name or video title).
+/// - `prefix`: The search prefix of `yt-dlp` for the site (e.g., `ytsearch`, `scsearch`).
+/// - `count`: The maximum count of results.
+///
+/// # Returns
+/// - `Ok(Vec<SearchResult>)`: The results, in the order of the site's search.
+/// - `Err(String)`: An error message if the search fails or finds nothing.
+pub fn search_results(
+    query: &str,
+    prefix: &str,
+    count: usize,
+) -> Result<Vec<SearchResult>, String> {
+    let results: Vec<SearchResult> = flat_playlist(&format!("{}{}:{}", prefix, count, query))?
+        .entries
+        .into_iter()
+        .filter_map(FlatEntry::into_result)
+        .collect();
+    if results.is_empty() {
+        return Err(format!("No results found for '{}'", query));
+    }
+    Ok(results)
+}
+
+/// Lists the videos of a YouTube playlist or channel using `yt-dlp`.
+///
+/// The tabs of channels (e.g., `Videos`, `Shorts`) are listed as well.
+///
+/// # Parameters
+/// - `url`: The URL of the playlist or channel.
+///
+/// # Returns
+/// - `Ok(Vec<SearchResult>)`: The videos, in the order of the playlist.
+/// - `Err(String)`: An error message if `yt-dlp` failed or the URL is not a playlist.
+pub fn playlist_entries(url: &str) -> Result<Vec<SearchResult>, String> {
+    let playlist = flat_playlist(url)?;
+    if playlist.entries.is_empty() {
+        return Err(format!("'{}' is not a playlist or it is empty.", url));
+    }
+
+    let mut videos = Vec::new();
+    for entry in playlist.entries {
+        // The entries of channels are their tabs, which are playlists too
+        if entry.ie_key.as_deref() == Some("YoutubeTab") {
+            if let Some(ref tab) = entry.url {
+                videos.extend(flat_playlist(tab)?.entries);
+            }
+        } else {
+            videos.push(entry);
+        }
+    }
+
+    Ok(videos
+        .into_iter()
+        .filter_map(FlatEntry::into_result)
+        .collect())
+}
+
+/// Fetches the flat playlist JSON of a URL with `yt-dlp`.
+fn flat_playlist(url: &str) -> Result<FlatEntry, String> {
+    let output = Command::new(program())
+        .arg("--flat-playlist") // List the entries without resolving them
+        .arg("--quiet") // Suppress unnecessary output
+        .arg("--dump-single-json") // Print the whole playlist as a single JSON
+        .arg(url)
+        .output()
+        .map_err(|_| "Unable to create process for listing playlist with 'yt-dlp'".to_string())?;
 
-    // Return the URL after removing any extra quotes and newlines
-    Ok(stdout.replace("\"", "").replace("\n", ""))
+    serde_json::from_slice(&output.stdout).map_err(|_| format!("Unable to list playlist '{}'", url))
 }
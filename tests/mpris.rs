@@ -0,0 +1,246 @@
+//! Integration test of the MPRIS service of the daemon, on a private D-Bus bus.
+//!
+//! It needs `dbus-daemon` and `mpv`, so it is ignored by default. Run it with:
+//! `cargo test --test mpris -- --ignored`
+#![cfg(feature = "mpris")]
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+use zbus::blocking::proxy::Builder;
+use zbus::blocking::{connection, Connection, Proxy};
+use zbus::proxy::CacheProperties;
+use zbus::zvariant::{ObjectPath, OwnedObjectPath, OwnedValue};
+
+/// The well-known name of the playit MPRIS service.
+const BUS_NAME: &str = "org.mpris.MediaPlayer2.playit";
+
+/// How long to wait for the daemon and mpv to react.
+const TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Stops mpv, kills the spawned processes and removes the temporary directory when the test
+/// ends, even if it fails.
+struct Cleanup {
+    children: Vec<Child>,
+    directory: PathBuf,
+}
+
+impl Drop for Cleanup {
+    fn drop(&mut self) {
+        // mpv is spawned by the daemon, so it isn't killed with it
+        if let Ok(mut mpv) = UnixStream::connect(self.directory.join("playit-mpv.sock")) {
+            let _ = mpv.set_read_timeout(Some(Duration::from_secs(1)));
+            let _ = mpv.write_all(b"{\"command\": [\"quit\"]}\n");
+            // Wait for the answer, so the command isn't dropped with the connection
+            let _ = BufReader::new(mpv).read_line(&mut String::new());
+        }
+        for child in &mut self.children {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        let _ = fs::remove_dir_all(&self.directory);
+    }
+}
+
+/// Waits until a condition holds, failing the test after `TIMEOUT`.
+fn wait_until(what: &str, mut condition: impl FnMut() -> bool) {
+    let started = Instant::now();
+    while !condition() {
+        assert!(
+            started.elapsed() < TIMEOUT,
+            "Timed out waiting for {}",
+            what
+        );
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// Writes a silent WAV file of the given length, so the test doesn't need any media.
+fn write_silence(path: &Path, seconds: u32) {
+    const RATE: u32 = 8000;
+    let size = RATE * seconds;
+    let mut content = Vec::new();
+    content.extend_from_slice(b"RIFF");
+    content.extend_from_slice(&(36 + size).to_le_bytes());
+    content.extend_from_slice(b"WAVEfmt ");
+    content.extend_from_slice(&16u32.to_le_bytes());
+    content.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    content.extend_from_slice(&1u16.to_le_bytes()); // Mono
+    content.extend_from_slice(&RATE.to_le_bytes());
+    content.extend_from_slice(&RATE.to_le_bytes()); // Bytes per second
+    content.extend_from_slice(&1u16.to_le_bytes()); // Bytes per sample
+    content.extend_from_slice(&8u16.to_le_bytes()); // Bits per sample
+    content.extend_from_slice(b"data");
+    content.extend_from_slice(&size.to_le_bytes());
+    content.resize(content.len() + size as usize, 128);
+    fs::write(path, content).unwrap();
+}
+
+/// Returns a playit command which uses only the files in the temporary directory.
+fn playit(directory: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_playit"));
+    command
+        .env("HOME", directory)
+        .env("XDG_RUNTIME_DIR", directory)
+        .env("XDG_CONFIG_HOME", directory.join("config"))
+        .env("XDG_DATA_HOME", directory.join("data"))
+        .env("XDG_CACHE_HOME", directory.join("cache"))
+        .arg("--config")
+        .arg(directory.join("config.toml"))
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    // Play without audio output, so the test works without a sound card
+    command.arg("--mpv-opt").arg("ao=null");
+    command
+}
+
+/// Reads a property of the MPRIS player, without the cache of zbus.
+fn player_property<T: TryFrom<OwnedValue>>(player: &Proxy, name: &str) -> Option<T>
+where
+    T::Error: Into<zbus::Error>,
+{
+    player.get_property(name).ok()
+}
+
+/// Returns the URL and the track id of the current media, from the MPRIS metadata.
+fn current_track(player: &Proxy) -> (Option<String>, Option<String>) {
+    let metadata: HashMap<String, OwnedValue> =
+        player_property(player, "Metadata").unwrap_or_default();
+    let url = metadata
+        .get("xesam:url")
+        .and_then(|url| String::try_from(url.try_clone().ok()?).ok());
+    let track_id = metadata
+        .get("mpris:trackid")
+        .and_then(|id| OwnedObjectPath::try_from(id.try_clone().ok()?).ok())
+        .map(|id| id.as_str().to_string());
+    (url, track_id)
+}
+
+/// Returns the position of the playback in seconds.
+fn position(player: &Proxy) -> f64 {
+    player_property::<i64>(player, "Position").unwrap_or(-1) as f64 / 1_000_000.0
+}
+
+#[test]
+#[ignore = "needs dbus-daemon and mpv"]
+fn daemon_is_controlled_over_mpris() {
+    let directory = std::env::temp_dir().join(format!("playit-mpris-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    fs::write(directory.join("config.toml"), "").unwrap();
+    let mut cleanup = Cleanup {
+        children: Vec::new(),
+        directory: directory.clone(),
+    };
+
+    // A private bus, so the test doesn't touch the session of the user
+    let mut bus = Command::new("dbus-daemon")
+        .args(["--session", "--nofork", "--print-address"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("dbus-daemon should be installed");
+    let mut address = String::new();
+    BufReader::new(bus.stdout.take().unwrap())
+        .read_line(&mut address)
+        .unwrap();
+    cleanup.children.push(bus);
+    let address = address.trim().to_string();
+
+    let daemon = playit(&directory)
+        .args(["daemon", "--mpris-bus", &address])
+        .spawn()
+        .unwrap();
+    cleanup.children.push(daemon);
+
+    let connection: Connection = connection::Builder::address(address.as_str())
+        .unwrap()
+        .build()
+        .unwrap();
+    let dbus = zbus::blocking::fdo::DBusProxy::new(&connection).unwrap();
+    wait_until("the MPRIS service", || {
+        dbus.name_has_owner(BUS_NAME.try_into().unwrap())
+            .unwrap_or(false)
+    });
+    let player: Proxy = Builder::new(&connection)
+        .destination(BUS_NAME)
+        .unwrap()
+        .path("/org/mpris/MediaPlayer2")
+        .unwrap()
+        .interface("org.mpris.MediaPlayer2.Player")
+        .unwrap()
+        .cache_properties(CacheProperties::No)
+        .build()
+        .unwrap();
+    assert_eq!(
+        player_property::<String>(&player, "PlaybackStatus").as_deref(),
+        Some("Stopped")
+    );
+
+    // Send two media to the daemon, like `--play <DIRECTORY>` does
+    let media = directory.join("media");
+    fs::create_dir_all(&media).unwrap();
+    let (first, second) = (media.join("1.wav"), media.join("2.wav"));
+    write_silence(&first, 60);
+    write_silence(&second, 60);
+    let status = playit(&directory)
+        .arg("--play")
+        .arg(&media)
+        .arg("--notification")
+        .arg("")
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    wait_until("the playback", || {
+        player_property::<String>(&player, "PlaybackStatus").as_deref() == Some("Playing")
+    });
+    let (url, first_track) = current_track(&player);
+    assert_eq!(url.as_deref(), first.to_str());
+    let first_track = first_track.expect("The metadata should have a track id.");
+
+    player.call_method("Next", &()).unwrap();
+    wait_until("the next media", || {
+        current_track(&player).0.as_deref() == second.to_str()
+    });
+    let second_track = current_track(&player).1.unwrap();
+    assert_ne!(second_track, first_track);
+    player.call_method("Previous", &()).unwrap();
+    wait_until("the previous media", || {
+        current_track(&player)
+            == (
+                first.to_str().map(str::to_string),
+                Some(first_track.clone()),
+            )
+    });
+
+    // Positions are in microseconds
+    player.call_method("Seek", &(10_000_000i64)).unwrap();
+    wait_until("the seek", || position(&player) >= 10.0);
+    let track = ObjectPath::try_from(first_track.as_str()).unwrap();
+    player
+        .call_method("SetPosition", &(track, 40_000_000i64))
+        .unwrap();
+    wait_until("the new position", || {
+        (40.0..50.0).contains(&position(&player))
+    });
+
+    player.call_method("PlayPause", &()).unwrap();
+    wait_until("the pause", || {
+        player_property::<String>(&player, "PlaybackStatus").as_deref() == Some("Paused")
+    });
+    player.call_method("PlayPause", &()).unwrap();
+    wait_until("the resume", || {
+        player_property::<String>(&player, "PlaybackStatus").as_deref() == Some("Playing")
+    });
+
+    // Stop rewinds and pauses, keeping the queue of the daemon
+    player.call_method("Stop", &()).unwrap();
+    wait_until("the stop", || {
+        player_property::<String>(&player, "PlaybackStatus").as_deref() != Some("Playing")
+            && position(&player) < 1.0
+    });
+    assert_eq!(current_track(&player).0.as_deref(), first.to_str());
+}